@@ -1,9 +1,85 @@
-use clap::{Parser, ValueEnum};
+use builder::pack::writer::WriterRegistry;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::{fmt::Display, path::PathBuf};
 
+/// Make sure `name` is a bundle format we can write,
+/// so that a bad format is caught before any job runs.
+fn parse_format(name: &str) -> Result<String, String> {
+    WriterRegistry::default()
+        .get(name)
+        .map(|_| name.to_owned())
+        .map_err(|e| e.to_string())
+}
+
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Options for building a bundle.
+    /// These are used when no command is given.
+    #[command(flatten)]
+    pub build: Option<BuildArgs>,
+
+    /// Log verbosity level.
+    #[arg(long, global = true, default_value_t = LogLevel::Info)]
+    pub log: LogLevel,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print information about a bundle file
+    Inspect {
+        /// The bundle to inspect
+        bundle: PathBuf,
+
+        /// List every file in this bundle
+        #[arg(long, default_value_t = false)]
+        list: bool,
+
+        /// Decompress every file in this bundle and check its hash
+        #[arg(long, default_value_t = false)]
+        verify: bool,
+    },
+
+    /// Serve bundle files over HTTP, for testing.
+    /// Each bundle is served at `/<file name>`.
+    Serve {
+        /// The bundles to serve
+        #[arg(required = true)]
+        bundles: Vec<PathBuf>,
+
+        /// The address to listen on
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        address: String,
+    },
+
+    /// Count the range requests needed to load a list of files from a bundle
+    Simulate {
+        /// The bundle to load files from
+        bundle: PathBuf,
+
+        /// The files to load. This may be a plain list of file names,
+        /// TeX `-recorder` output (an `.fls` file), or a Tectonic log.
+        trace: PathBuf,
+
+        /// Merge two requests if there are at most this many bytes between them
+        #[arg(long, default_value_t = 0)]
+        max_gap: u64,
+
+        /// Never make a request longer than this many bytes
+        #[arg(long)]
+        max_request: Option<u64>,
+
+        /// Fetch files in bundle order instead of trace order
+        #[arg(long, default_value_t = false)]
+        sorted: bool,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct BuildArgs {
     /// Which job we should run. `all` is default,
     /// but single jobs can be run on their own for debugging.
     #[arg(long, default_value_t = BundleJob::All)]
@@ -18,13 +94,32 @@ pub struct Cli {
     pub build_dir: PathBuf,
 
     /// What kind of bundle should we produce?
+    /// One of `v1`, `v1-wide`, `zip`, `itar`, or `dir`.
     /// This only has an effect when running jobs `all` or `pack`
-    #[arg(default_value_t = BundleFormat::BundleV1)]
-    pub format: BundleFormat,
+    #[arg(default_value = "v1", value_parser = parse_format)]
+    pub format: String,
 
-    /// Log verbosity level.
-    #[arg(long, default_value_t = LogLevel::Info)]
-    pub log: LogLevel,
+    /// Access traces used to lay out bundle files.
+    /// Files opened in these traces are placed first, so that files
+    /// loaded together are next to each other in the bundle.
+    /// Traces have the same format as the `simulate` command's.
+    /// This only has an effect when running jobs `all` or `pack`
+    #[arg(long)]
+    pub trace: Vec<PathBuf>,
+
+    /// Split the bundle into part files of at most this many bytes.
+    /// The `.ttb` file then only holds the bundle's header and index,
+    /// and files are stored in `.ttb.000`, `.ttb.001`, and so on.
+    /// This only has an effect when running jobs `all` or `pack`
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub part_size: Option<u64>,
+
+    /// Where to write the bundle, instead of `<build dir>/<name>.ttb`.
+    /// Use `-` to write to stdout. Bundles written to stdout or a pipe
+    /// take longer to pack, since every file is compressed twice.
+    /// This only has an effect when running jobs `all` or `pack`
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
 
     /// If this flag is set, don't fail when an input's hash doesn't match
     /// the hash specified in the bundle's configuration file.
@@ -77,6 +172,11 @@ pub enum BundleJob {
     /// (Stage 2) Pack selected files into a bundle
     #[value(name = "pack")]
     Pack,
+
+    /// Export selected files as a TDS tree, for use with kpathsea.
+    /// This isn't run by `all`.
+    #[value(name = "export")]
+    Export,
 }
 
 impl Display for BundleJob {
@@ -85,6 +185,7 @@ impl Display for BundleJob {
             Self::All => write!(f, "all"),
             Self::Select => write!(f, "select"),
             Self::Pack => write!(f, "pack"),
+            Self::Export => write!(f, "export"),
         }
     }
 }
@@ -97,19 +198,8 @@ impl BundleJob {
     pub fn do_pack(&self) -> bool {
         matches!(self, Self::All | Self::Pack)
     }
-}
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-pub enum BundleFormat {
-    #[value(name = "v1")]
-    BundleV1,
-}
-
-impl Display for BundleFormat {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::BundleV1 => write!(f, "v1")?,
-        }
-        Ok(())
+    pub fn do_export(&self) -> bool {
+        matches!(self, Self::Export)
     }
 }
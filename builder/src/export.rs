@@ -0,0 +1,220 @@
+use crate::{
+    pack::{link_or_copy, read_filelist_paths},
+    select::spec::{BundleSearchOrder, BundleSpec},
+};
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+/// kpathsea variables we point at this tree's search path
+const KPSE_VARS: &[&str] = &[
+    "TEXINPUTS",
+    "MFINPUTS",
+    "MPINPUTS",
+    "BIBINPUTS",
+    "BSTINPUTS",
+    "TFMFONTS",
+    "VFFONTS",
+    "T1FONTS",
+    "AFMFONTS",
+    "OPENTYPEFONTS",
+    "TTFONTS",
+    "ENCFONTS",
+    "TEXFONTMAPS",
+];
+
+/// Where an input's files go in a TDS tree
+fn tds_roots(spec: &BundleSpec) -> HashMap<&str, PathBuf> {
+    spec.inputs
+        .iter()
+        .map(|(name, input)| (name.as_str(), input.tds_root.clone().unwrap_or_default()))
+        .collect()
+}
+
+/// Map a content path (`<input>/<path>`) to a path in a TDS tree.
+/// Returns `None` for files that aren't in an input, like `SEARCH`.
+fn tds_path(roots: &HashMap<&str, PathBuf>, path: &str) -> Option<PathBuf> {
+    let (input, rest) = path.split_once('/')?;
+    Some(roots.get(input)?.join(rest))
+}
+
+/// Map a search rule (like `/texlive/tex//`) to a kpathsea path element.
+fn kpse_rule(roots: &HashMap<&str, PathBuf>, rule: &str) -> Option<String> {
+    let (rule, recursive) = match rule.strip_suffix("//") {
+        Some(x) => (x, "//"),
+        None => (rule.trim_end_matches('/'), ""),
+    };
+    let rule = rule.trim_start_matches('/');
+
+    let (input, rest) = rule.split_once('/').unwrap_or((rule, ""));
+    let root = roots.get(input)?.join(rest);
+    let root = root.to_str()?.trim_end_matches('/');
+
+    if root.is_empty() {
+        Some(format!("$TEXMFBUNDLE{recursive}"))
+    } else {
+        Some(format!("$TEXMFBUNDLE/{root}{recursive}"))
+    }
+}
+
+/// Write a kpathsea `ls-R` database for the tree at `root`
+fn write_ls_r(root: &Path) -> Result<()> {
+    let mut out = BufWriter::new(File::create(root.join("ls-R"))?);
+    writeln!(
+        out,
+        "% ls-R -- filename database for kpathsea; do not change this line."
+    )?;
+
+    for entry in WalkDir::new(root).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+
+        let rel = entry.path().strip_prefix(root)?;
+        if rel.as_os_str().is_empty() {
+            writeln!(out, "\n./:")?;
+        } else {
+            writeln!(out, "\n./{}:", rel.display())?;
+        }
+
+        let mut names = Vec::new();
+        for child in fs::read_dir(entry.path())? {
+            names.push(child?.file_name());
+        }
+        names.sort();
+        for name in names {
+            writeln!(out, "{}", name.to_string_lossy())?;
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+/// Export the files in `<build dir>/content` as a TDS tree in `<build dir>/texmf`,
+/// with `ls-R` and a `texmf.cnf` fragment that searches it like Tectonic would.
+pub fn export(spec: &BundleSpec, build_dir: &Path) -> Result<()> {
+    let content_dir = build_dir.join("content");
+    let texmf = build_dir.join("texmf");
+    let roots = tds_roots(spec);
+
+    if texmf.exists() {
+        warn!(
+            tectonic_log_source = "export",
+            "`{}` exists, removing",
+            texmf.display()
+        );
+        fs::remove_dir_all(&texmf)?;
+    }
+
+    info!(
+        tectonic_log_source = "export",
+        "Exporting TDS tree to `{}`...",
+        texmf.display()
+    );
+
+    // Files in inputs that come first in the search order win conflicts.
+    // Content paths are `<input>/<path>`, so order paths by input.
+    let rank: HashMap<&str, usize> = spec
+        .bundle
+        .search_order
+        .iter()
+        .filter_map(|x| match x {
+            BundleSearchOrder::Input { input } => Some(input.as_str()),
+            BundleSearchOrder::Plain(_) => None,
+        })
+        .enumerate()
+        .map(|(i, x)| (x, i))
+        .collect();
+    let mut paths = read_filelist_paths(&content_dir)?;
+    paths.sort_by_cached_key(|x| {
+        let input = x.split_once('/').map(|x| x.0).unwrap_or("");
+        (rank.get(input).copied().unwrap_or(usize::MAX), x.clone())
+    });
+
+    let mut placed: HashMap<PathBuf, String> = HashMap::new();
+    let mut copied = 0usize;
+    for path in &paths {
+        let tds = match tds_path(&roots, path) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        if let Some(other) = placed.get(&tds) {
+            warn!(
+                tectonic_log_source = "export",
+                "`{path}` and `{other}` both map to `{}`, keeping `{other}`",
+                tds.display()
+            );
+            continue;
+        }
+
+        let dst = texmf.join(&tds);
+        fs::create_dir_all(dst.parent().unwrap())
+            .with_context(|| format!("while creating directory for `{}`", tds.display()))?;
+        if link_or_copy(&content_dir.join(path), &dst)? {
+            copied += 1;
+        }
+        placed.insert(tds, path.clone());
+    }
+
+    info!(
+        tectonic_log_source = "export",
+        "exported {} files ({copied} copied)",
+        placed.len()
+    );
+
+    write_ls_r(&texmf)?;
+
+    // Map search rules to a kpathsea path, in order
+    let mut path = vec![".".to_owned()];
+    for rule in fs::read_to_string(content_dir.join("SEARCH"))?.lines() {
+        // The content root only holds bundle metadata
+        if rule.trim_matches('/').is_empty() {
+            continue;
+        }
+
+        match kpse_rule(&roots, rule) {
+            Some(x) => {
+                if !path.contains(&x) {
+                    path.push(x)
+                }
+            }
+            None => warn!(
+                tectonic_log_source = "export",
+                "search rule `{rule}` isn't in an input, leaving it out of texmf.cnf"
+            ),
+        }
+    }
+
+    let mut cnf = BufWriter::new(File::create(build_dir.join("texmf.cnf"))?);
+    writeln!(
+        cnf,
+        "% kpathsea configuration for bundle `{}`",
+        spec.bundle.name
+    )?;
+    writeln!(
+        cnf,
+        "% Generated by {}, do not edit.",
+        env!("CARGO_PKG_NAME")
+    )?;
+    writeln!(cnf)?;
+    writeln!(cnf, "TEXMFBUNDLE = {}", texmf.canonicalize()?.display())?;
+    writeln!(cnf, "TEXMF = $TEXMFBUNDLE")?;
+    writeln!(cnf, "TEXMFDBS = $TEXMFBUNDLE")?;
+    writeln!(cnf, "TEXMFBUNDLEPATH = {}", path.join(";"))?;
+    writeln!(cnf)?;
+    for var in KPSE_VARS {
+        writeln!(cnf, "{var} = $TEXMFBUNDLEPATH")?;
+    }
+    cnf.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Hex strings, as used for hashes in bundles and provenance records.
+
+use anyhow::{bail, Result};
+use std::fmt::Write;
+
+/// Encode `bytes` as a lowercase hex string
+pub fn encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(out, "{b:02x}");
+    }
+    out
+}
+
+/// Decode a string of hex digits into bytes
+pub fn decode(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        bail!("`{s}` has an odd number of hex digits");
+    }
+    if !s.bytes().all(|x| x.is_ascii_hexdigit()) {
+        bail!("`{s}` is not a hex string");
+    }
+
+    Ok((0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let bytes = [0x00, 0x01, 0x7f, 0xab, 0xff];
+        assert_eq!(encode(&bytes), "00017fabff");
+        assert_eq!(decode("00017fabff").unwrap(), bytes);
+        assert_eq!(decode("00017FABFF").unwrap(), bytes);
+        assert!(decode("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn bad_hex() {
+        assert!(decode("abc").is_err());
+        assert!(decode("zz").is_err());
+        assert!(decode("+1").is_err());
+        // Multi-byte characters must not panic on slicing
+        assert!(decode("éé").is_err());
+    }
+}
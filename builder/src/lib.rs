@@ -0,0 +1,20 @@
+//! Tools for building, packing, and reading Tectonic bundles.
+//!
+//! The `builder` binary is a thin wrapper around this library:
+//! - [`select::spec::BundleSpec`] parses bundle specifications,
+//! - [`select::picker::FilePicker`] selects and patches files into a build dir,
+//! - [`pack`] turns a build dir into a bundle,
+//! - [`read::Bundle`] reads files from a finished bundle.
+
+pub mod export;
+pub mod hex;
+pub mod pack;
+pub mod provenance;
+pub mod read;
+pub mod search;
+pub mod select;
+pub mod serve;
+pub mod simulate;
+pub mod trace;
+
+pub use read::Bundle;
@@ -1,14 +1,24 @@
-use crate::{
-    pack::bundlev1::BundleV1,
+use anyhow::{bail, Context, Result};
+use builder::{
+    export, hex,
+    pack::{
+        self,
+        bundledir::BundleDir,
+        writer::{BundleContent, PackOptions, WriterRegistry},
+    },
+    provenance::{self, Provenance, PROVENANCE_FILE},
+    read::bundlev1::BundleV1Reader,
     select::{picker::FilePicker, spec::BundleSpec},
+    serve, simulate,
 };
-use anyhow::{Context, Result};
 use clap::Parser;
 use log::LogFormatter;
+use sha2::{Digest, Sha256};
 use std::{
     cmp::Ordering,
     fs::{self, File},
     io::Read,
+    path::Path,
     thread,
     time::Duration,
 };
@@ -16,10 +26,8 @@ use tracing::{error, info, warn, Level};
 
 mod cli;
 mod log;
-mod pack;
-mod select;
 
-fn select(cli: &cli::Cli) -> Result<()> {
+fn select(cli: &cli::BuildArgs) -> Result<()> {
     let bundle_dir = cli
         .bundle_spec
         .canonicalize()
@@ -81,7 +89,7 @@ fn select(cli: &cli::Cli) -> Result<()> {
     // Run selector
     let sources: Vec<String> = picker.iter_sources().map(|x| x.to_string()).collect();
     for source in sources {
-        picker.add_source(cli, &source)?;
+        picker.add_source(&source, cli.allow_hash_mismatch)?;
     }
     picker.finish(true)?;
 
@@ -109,11 +117,10 @@ fn select(cli: &cli::Cli) -> Result<()> {
     }
 
     // Check output hash
+    let mut hash = String::new();
+    File::open(cli.build_dir.join("content/SHA256SUM"))?.read_to_string(&mut hash)?;
+    let hash = hash.trim();
     {
-        let mut file = File::open(cli.build_dir.join("content/SHA256SUM"))?;
-        let mut hash = String::new();
-        file.read_to_string(&mut hash)?;
-        let hash = hash.trim();
         if hash != bundle_config.bundle.expected_hash {
             warn!(
                 tectonic_log_source = "select",
@@ -133,14 +140,39 @@ fn select(cli: &cli::Cli) -> Result<()> {
         }
     }
 
+    // Write provenance
+    {
+        let spec_hash = hex::encode(&Sha256::digest(file_str.as_bytes()));
+
+        let provenance = Provenance {
+            bundle_name: &bundle_config.bundle.name,
+            spec_hash: &spec_hash,
+            bundle_hash: hash,
+            inputs: &picker.inputs,
+        };
+
+        provenance
+            .write(&cli.build_dir.join("provenance.json"))
+            .context("while writing provenance")?;
+        if bundle_config.bundle.embed_provenance.unwrap_or(false) {
+            provenance
+                .write(&cli.build_dir.join("content").join(PROVENANCE_FILE))
+                .context("while writing provenance")?;
+        }
+    }
+
     Ok(())
 }
 
-fn pack(cli: &cli::Cli) -> Result<()> {
-    let mut file = File::open(&cli.bundle_spec)?;
-    let mut file_str = String::new();
-    file.read_to_string(&mut file_str)?;
-    let bundle_config: BundleSpec = toml::from_str(&file_str)?;
+fn pack(cli: &cli::BuildArgs) -> Result<()> {
+    let bundle_dir = cli
+        .bundle_spec
+        .canonicalize()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    let bundle_config = BundleSpec::load(&cli.bundle_spec)?;
 
     if !cli.build_dir.join("content").is_dir() {
         error!(
@@ -150,27 +182,193 @@ fn pack(cli: &cli::Cli) -> Result<()> {
         return Ok(());
     }
 
-    let target_name = format!("{}.ttb", &bundle_config.bundle.name);
-    let target = cli.build_dir.join(&target_name);
-    if target.exists() {
+    let registry = WriterRegistry::default();
+    let format = registry.get(&cli.format)?;
+
+    // `-` means stdout, which we stream to
+    let to_stdout = cli.output.as_ref().map(|x| x.as_os_str() == "-") == Some(true);
+
+    let target = match &cli.output {
+        Some(x) if !to_stdout => x.clone(),
+        _ => cli.build_dir.join(match format.extension {
+            Some(ext) => format!("{}.{ext}", &bundle_config.bundle.name),
+            None => bundle_config.bundle.name.clone(),
+        }),
+    };
+    let target_name = target.display();
+
+    let content_dir = cli.build_dir.join("content");
+    let mut content = BundleContent::load(&content_dir)?;
+    content.meta = pack::make_meta(&bundle_config, &bundle_dir)?;
+
+    let mut writer = (format.constructor)(&PackOptions {
+        target: target.clone(),
+        stdout: to_stdout,
+        prefetch: pack::prefetch_groups(&bundle_config, &bundle_dir, &content_dir)?,
+        layout: pack::trace_layout(&content_dir, &cli.trace)?,
+        part_size: cli.part_size,
+    })?;
+
+    // Streams (stdout, pipes, and devices) aren't files we replace,
+    // so we leave any old parts next to them alone unless we're writing new ones.
+    let seekable = !to_stdout
+        && fs::metadata(&target)
+            .map(|x| x.is_file() || x.is_dir())
+            .unwrap_or(true);
+
+    if !to_stdout && target.exists() {
         if target.is_file() {
             warn!("target bundle `{target_name}` exists, removing");
             fs::remove_file(&target)?;
-        } else {
-            error!("target bundle `{target_name}` exists and isn't a file, can't continue");
+        } else if target.is_dir() && format.extension.is_none() {
+            // Never remove a directory we didn't make
+            if !BundleDir::is_bundle_dir(&target)? {
+                error!("target `{target_name}` is a directory that isn't a directory bundle, can't continue");
+                return Ok(());
+            }
+            warn!("target bundle `{target_name}` exists, removing");
+            fs::remove_dir_all(&target)?;
+        } else if target.is_dir() {
+            error!("target bundle `{target_name}` is a directory, can't continue");
             return Ok(());
         }
     }
+    if seekable || cli.part_size.is_some() {
+        for part in pack::part_files(&target)? {
+            warn!("removing old bundle part `{}`", part.display());
+            fs::remove_file(&part)?;
+        }
+    }
+
+    let artifacts = writer.write(&content)?;
 
-    match cli.format {
-        cli::BundleFormat::BundleV1 => {
-            BundleV1::make(Box::new(File::create(target)?), cli.build_dir.clone())?
+    // Record the packed bundle in provenance, if select made one
+    let provenance = cli.build_dir.join("provenance.json");
+    if provenance.is_file() {
+        for artifact in artifacts.iter().filter(|x| x.is_file()) {
+            provenance::add_artifact(&provenance, artifact, &cli.format)
+                .context("while updating provenance")?;
         }
     }
 
     Ok(())
 }
 
+fn export(cli: &cli::BuildArgs) -> Result<()> {
+    let bundle_config = BundleSpec::load(&cli.bundle_spec)?;
+
+    if !cli.build_dir.join("content").is_dir() {
+        bail!(
+            "content directory `{}/content` doesn't exist, can't continue",
+            cli.build_dir.display()
+        );
+    }
+
+    export::export(&bundle_config, &cli.build_dir)
+}
+
+fn inspect(bundle: &Path, list: bool, verify: bool) -> Result<()> {
+    let mut reader = BundleV1Reader::open(bundle)?;
+    let header = &reader.header;
+    let index = &reader.index;
+
+    println!("version:      {}", header.version);
+    println!("hash:         {}", header.hash_string());
+    println!(
+        "index:        {} bytes at {} ({} bytes uncompressed)",
+        header.index_gzip_len, header.index_start, header.index_real_len
+    );
+    println!("files:        {}", index.filelist.len());
+    println!(
+        "content size: {} bytes ({} bytes uncompressed)",
+        index.filelist.iter().map(|x| x.gzip_len).sum::<u64>(),
+        index.filelist.iter().map(|x| x.real_len).sum::<u64>()
+    );
+
+    let mut search = Vec::from_iter(index.search.keys());
+    search.sort();
+    for name in search {
+        println!(
+            "search:       {name}{}",
+            if *name == index.default_search {
+                " (default)"
+            } else {
+                ""
+            }
+        );
+    }
+
+    for part in &index.parts {
+        println!(
+            "part:         {} ({} bytes at {})",
+            part.name, part.len, part.start
+        );
+    }
+
+    let mut prefetch = Vec::from_iter(&index.prefetch);
+    prefetch.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, group) in prefetch {
+        println!(
+            "prefetch:     {name} ({} files, {} bytes at {})",
+            group.members.len(),
+            group.len,
+            group.start
+        );
+    }
+
+    if index.meta.is_empty() {
+        println!("this bundle has no metadata");
+    } else {
+        println!("metadata:");
+        for (k, v) in &index.meta {
+            println!("    {k}: {v}");
+        }
+    }
+
+    if list {
+        for entry in &index.filelist {
+            println!(
+                "{} {} {} {} {}",
+                entry.start,
+                entry.gzip_len,
+                entry.real_len,
+                entry.hash.as_deref().unwrap_or("nohash"),
+                entry.path
+            );
+        }
+    }
+
+    if verify {
+        let filelist = index.filelist.clone();
+        let mut bad = 0usize;
+        for entry in &filelist {
+            let data = reader.read_file(entry)?;
+            let hash = hex::encode(&Sha256::digest(&data));
+
+            if data.len() != entry.real_len as usize
+                || entry.hash.as_ref().map(|x| *x != hash).unwrap_or(false)
+            {
+                error!(
+                    tectonic_log_source = "inspect",
+                    "`{}` does not match the bundle index", entry.path
+                );
+                bad += 1;
+            }
+        }
+
+        if bad != 0 {
+            bail!("{bad} files in this bundle are corrupt");
+        }
+        info!(
+            tectonic_log_source = "inspect",
+            "all {} files match the bundle index",
+            filelist.len()
+        );
+    }
+
+    Ok(())
+}
+
 #[allow(clippy::needless_return)]
 fn main() -> Result<()> {
     let cli = cli::Cli::parse();
@@ -183,8 +381,39 @@ fn main() -> Result<()> {
             cli::LogLevel::Error => Level::ERROR,
         })
         .event_format(LogFormatter::new(true))
+        // Logs go to stderr, so that bundles may be written to stdout
+        .with_writer(std::io::stderr)
         .init();
 
+    if let Some(command) = &cli.command {
+        return match command {
+            cli::Command::Inspect {
+                bundle,
+                list,
+                verify,
+            } => inspect(bundle, *list, *verify),
+            cli::Command::Serve { bundles, address } => serve::serve(bundles, address),
+            cli::Command::Simulate {
+                bundle,
+                trace,
+                max_gap,
+                max_request,
+                sorted,
+            } => simulate::simulate(
+                bundle,
+                trace,
+                &simulate::CoalesceRules {
+                    max_gap: *max_gap,
+                    max_request: *max_request,
+                    sorted: *sorted,
+                },
+            ),
+        };
+    }
+
+    // Clap makes sure we have build args if we have no command
+    let cli = cli.build.unwrap();
+
     if cli.job.do_select() {
         match select(&cli) {
             Ok(_) => {}
@@ -211,5 +440,18 @@ fn main() -> Result<()> {
         };
     }
 
+    if cli.job.do_export() {
+        match export(&cli) {
+            Ok(_) => {}
+            Err(e) => {
+                error!(
+                    tectonic_log_source = "export",
+                    "export job failed with error: {e}"
+                );
+                return Err(e);
+            }
+        };
+    }
+
     Ok(())
 }
@@ -0,0 +1,105 @@
+use super::{
+    flat_files, link_or_copy,
+    writer::{BundleContent, BundleWriter, PackOptions},
+};
+use anyhow::{bail, Context, Result};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+/// A flat directory bundle, for local use with Tectonic.
+///
+/// Every file is placed at the top level of this directory, by name,
+/// just like a zip bundle. Files are hardlinked from the content dir
+/// if possible, and copied otherwise.
+pub struct BundleDir {
+    target: PathBuf,
+}
+
+impl BundleWriter for BundleDir {
+    fn write(&mut self, content: &BundleContent) -> Result<Vec<PathBuf>> {
+        Self::make(&self.target, content)?;
+        Ok(vec![self.target.clone()])
+    }
+}
+
+/// The name of this bundle's index, which maps names to content paths
+const INDEX_FILE: &str = "INDEX";
+
+impl BundleDir {
+    /// Make a writer for directory bundles
+    pub fn writer(options: &PackOptions) -> Result<Box<dyn BundleWriter>> {
+        Ok(Box::new(Self {
+            target: options.simple_target("dir")?.to_path_buf(),
+        }))
+    }
+
+    /// Was the directory at `path` made by this writer?
+    /// We only replace directories that were, since they contain
+    /// nothing but links to the content dir and its metadata files.
+    pub fn is_bundle_dir(path: &Path) -> Result<bool> {
+        if !path.join(INDEX_FILE).is_file() || !path.join("SHA256SUM").is_file() {
+            return Ok(false);
+        }
+
+        // Directory bundles are flat
+        for entry in fs::read_dir(path)? {
+            if entry?.file_type()?.is_dir() {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn make(target: &Path, content: &BundleContent) -> Result<()> {
+        let files = flat_files(content);
+
+        // Our index shares this directory with bundle files
+        if files.iter().any(|(name, _)| name == INDEX_FILE) {
+            bail!("this bundle has a file named `{INDEX_FILE}`, which can't be stored in a directory bundle");
+        }
+
+        info!(
+            tectonic_log_source = "pack",
+            "Building directory bundle with {} files...",
+            files.len()
+        );
+
+        fs::create_dir_all(target)
+            .with_context(|| format!("while creating `{}`", target.display()))?;
+
+        // Maps names in this directory to paths in the content dir
+        let mut index = BufWriter::new(File::create(target.join(INDEX_FILE))?);
+
+        let mut copied = 0usize;
+        for (name, path) in &files {
+            let copy = match content.file_path(path) {
+                Some(src) => link_or_copy(&src, &target.join(name))?,
+                None => {
+                    let mut dst = File::create(target.join(name))?;
+                    std::io::copy(&mut content.open(path)?, &mut dst)
+                        .with_context(|| format!("while writing `{path}`"))?;
+                    true
+                }
+            };
+            if copy {
+                copied += 1;
+            }
+
+            writeln!(index, "{name} {path}")?;
+        }
+        index.flush()?;
+
+        info!(
+            tectonic_log_source = "pack",
+            "linked {} files, copied {copied}",
+            files.len() - copied
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use super::{
+    flat_files,
+    writer::{BundleContent, BundleWriter, PackOptions},
+};
+use anyhow::{Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, Write},
+    path::PathBuf,
+};
+use tar::{Builder, Header};
+use tracing::{info, warn};
+
+/// A legacy indexed tar bundle, as read by older Tectonic releases.
+///
+/// This is a flat tar file, where every file is stored at the top level by name,
+/// plus a gzipped index with one `<name> <offset> <length>` line per file.
+/// `<offset>` is the position of the file's first byte in the tar file.
+pub struct BundleItar {
+    target: PathBuf,
+}
+
+impl BundleWriter for BundleItar {
+    fn write(&mut self, content: &BundleContent) -> Result<Vec<PathBuf>> {
+        // The index goes next to the tar file
+        let mut index = self.target.clone().into_os_string();
+        index.push(".index.gz");
+        let index = PathBuf::from(index);
+
+        Self::make(
+            File::create(&self.target)?,
+            BufWriter::new(File::create(&index)?),
+            content,
+        )?;
+        Ok(vec![self.target.clone(), index])
+    }
+}
+
+impl BundleItar {
+    /// Make a writer for itar bundles
+    pub fn writer(options: &PackOptions) -> Result<Box<dyn BundleWriter>> {
+        Ok(Box::new(Self {
+            target: options.simple_target("itar")?.to_path_buf(),
+        }))
+    }
+
+    pub fn make(
+        target: impl Write + Seek,
+        index: impl Write,
+        content: &BundleContent,
+    ) -> Result<()> {
+        let files = flat_files(content);
+
+        info!(
+            tectonic_log_source = "pack",
+            "Building itar bundle with {} files...",
+            files.len()
+        );
+
+        let mut tar = Builder::new(target);
+        let mut index = GzEncoder::new(index, Compression::default());
+
+        for (name, path) in files {
+            // The index can't hold names with spaces
+            if name.contains(char::is_whitespace) {
+                warn!(
+                    tectonic_log_source = "pack",
+                    "`{path}` has whitespace in its name, leaving it out of this itar bundle"
+                );
+                continue;
+            }
+
+            let mut file = content.open(&path)?;
+            let len = content.size(&path)?;
+
+            // Use fixed metadata, so that itar bundles are reproducible
+            let mut header = Header::new_gnu();
+            header.set_size(len);
+            header.set_mode(0o644);
+            header.set_mtime(0);
+
+            tar.append_data(&mut header, &name, &mut file)
+                .with_context(|| format!("while writing `{path}`"))?;
+
+            // File data is padded to 512 bytes, and comes
+            // right before our position in the tar file.
+            let end = tar.get_mut().stream_position()?;
+            let offset = end - len.div_ceil(512) * 512;
+            writeln!(index, "{name} {offset} {len}")?;
+        }
+
+        tar.into_inner()?;
+        index.finish()?;
+        Ok(())
+    }
+}
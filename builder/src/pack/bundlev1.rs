@@ -1,10 +1,15 @@
-use anyhow::{bail, Result};
+use super::{
+    writer::{BundleContent, BundleFile, BundleWriter, PackOptions},
+    PrefetchGroup,
+};
+use crate::hex;
+use anyhow::{bail, Context, Result};
 use flate2::{write::GzEncoder, Compression};
 use std::{
+    collections::HashMap,
     fmt::Display,
-    fs::{self, File},
-    io::{stdout, BufRead, BufReader, Read, Seek, Write},
-    num::ParseIntError,
+    fs::File,
+    io::{stdout, BufWriter, Seek, Write},
     path::PathBuf,
 };
 use tracing::info;
@@ -12,28 +17,51 @@ use tracing::info;
 pub trait WriteSeek: std::io::Write + Seek {}
 impl<T: Write + Seek> WriteSeek for T {}
 
-pub fn decode_hex(s: &str) -> Result<Vec<u8>, ParseIntError> {
-    (0..s.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
-        .collect()
+/// Where we write a bundle
+pub enum BundleTarget {
+    /// A seekable file. We write the header last, after we know where the index is.
+    Seekable(Box<dyn WriteSeek>),
+
+    /// A stream, like stdout or a pipe.
+    /// We can't seek back to write the header, so every file is compressed twice:
+    /// once to find the index's position, and once to write it.
+    Stream(Box<dyn Write>),
+}
+
+impl Write for BundleTarget {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Seekable(x) => x.write(buf),
+            Self::Stream(x) => x.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Seekable(x) => x.flush(),
+            Self::Stream(x) => x.flush(),
+        }
+    }
 }
 
 // Size of ttbv1 header
-const HEADER_SIZE: u64 = 66u64;
+pub const HEADER_SIZE: u64 = 66u64;
+
+// Size of a wide ttbv1 header, which has 64-bit index lengths
+pub const WIDE_HEADER_SIZE: u64 = 74u64;
 
 #[derive(Debug)]
 struct FileListEntry {
     path: PathBuf,
-    hash: String,
+    hash: Option<String>,
     start: u64,
 
     // We need the compressed length to build
     // a range request for this bundle. We also
     // keep the real length around for performance
     // (we'll only need to allocate vectors once)
-    real_len: u32,
-    gzip_len: u32,
+    real_len: u64,
+    gzip_len: u64,
 }
 
 impl Display for FileListEntry {
@@ -43,177 +71,592 @@ impl Display for FileListEntry {
             self.start,
             self.gzip_len,
             self.real_len,
-            self.hash,
+            self.hash.as_deref().unwrap_or("nohash"),
             self.path.to_str().unwrap()
         )
         .fmt(f)
     }
 }
 
-pub struct BundleV1 {
+/// Options for building a ttbv1 bundle
+pub struct BundleV1Options {
+    /// Groups of files to write first, each stored contiguously.
+    /// A file in many groups is stored with the first.
+    pub prefetch: Vec<PrefetchGroup>,
+
+    /// Paths of files to write after prefetch groups, in this order.
+    /// All other files are written after these, in FILELIST order.
+    pub layout: Vec<String>,
+
+    /// If set, split this bundle's files into part files
+    pub parts: Option<PartOptions>,
+
+    /// If true, make a wide bundle with 64-bit index lengths.
+    /// Normal bundles can't hold files or indices larger than 4 GiB.
+    pub wide: bool,
+}
+
+impl BundleV1Options {
+    /// The version number we write in this bundle's header.
+    /// Split bundles are version 2, since older readers can't load them.
+    /// Wide bundles are version 3, and may or may not be split.
+    fn version(&self) -> u32 {
+        if self.wide {
+            3
+        } else if self.parts.is_some() {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// The size of this bundle's header
+    fn header_size(&self) -> u64 {
+        if self.wide {
+            WIDE_HEADER_SIZE
+        } else {
+            HEADER_SIZE
+        }
+    }
+}
+
+/// Options for splitting a bundle into part files
+pub struct PartOptions {
+    /// Maximum size of each part, in bytes
+    pub max_size: u64,
+
+    /// Parts are written to `<path>.000`, `<path>.001`, and so on.
+    pub path: PathBuf,
+}
+
+/// A part file we've written
+struct PartEntry {
+    /// The position of this part's first byte
+    /// in the concatenation of all parts
+    start: u64,
+    len: u64,
+    name: String,
+}
+
+pub struct BundleV1<'a> {
     filelist: Vec<FileListEntry>,
-    target: Box<dyn WriteSeek>,
-    content_dir: PathBuf,
+    options: BundleV1Options,
+    target: BundleTarget,
+
+    /// Parts we've written, and the one we're writing
+    parts: Vec<PartEntry>,
+    part_file: Option<File>,
+
+    content: &'a BundleContent,
 
     index_start: u64,
-    index_real_len: u32,
-    index_gzip_len: u32,
+    index_real_len: u64,
+    index_gzip_len: u64,
 }
 
-impl BundleV1 {
-    pub fn make(target: Box<dyn WriteSeek>, build_dir: PathBuf) -> Result<()> {
-        let mut bundle = BundleV1::new(target, build_dir)?;
+/// Writes ttbv1 bundles to a file or stdout
+pub struct BundleV1Writer {
+    /// Where to write the bundle. `None` means stdout.
+    target: Option<PathBuf>,
+    options: Option<BundleV1Options>,
+}
 
-        bundle.add_files()?;
-        bundle.write_index()?;
-        bundle.write_header()?;
+impl BundleWriter for BundleV1Writer {
+    fn write(&mut self, content: &BundleContent) -> Result<Vec<PathBuf>> {
+        let options = match self.options.take() {
+            Some(x) => x,
+            None => bail!("this writer has already been used"),
+        };
+
+        // Pipes and stdout can't seek, so we stream to them
+        let target = match &self.target {
+            None => BundleTarget::Stream(Box::new(BufWriter::new(stdout().lock()))),
+            Some(path) => {
+                let file = File::create(path)?;
+                if file.metadata()?.is_file() {
+                    BundleTarget::Seekable(Box::new(file))
+                } else {
+                    BundleTarget::Stream(Box::new(BufWriter::new(file)))
+                }
+            }
+        };
 
-        Ok(())
+        let parts = BundleV1::make(target, content, options)?;
+        Ok(self.target.iter().cloned().chain(parts).collect())
+    }
+}
+
+impl<'a> BundleV1<'a> {
+    /// Make a writer for ttbv1 bundles.
+    /// If `wide` is true, make wide bundles with 64-bit index lengths.
+    pub fn writer(options: &PackOptions, wide: bool) -> Result<Box<dyn BundleWriter>> {
+        Ok(Box::new(BundleV1Writer {
+            target: (!options.stdout).then(|| options.target.clone()),
+            options: Some(BundleV1Options {
+                prefetch: options.prefetch.clone(),
+                layout: options.layout.clone(),
+                parts: options.part_size.map(|max_size| PartOptions {
+                    max_size,
+                    path: options.target.clone(),
+                }),
+                wide,
+            }),
+        }))
+    }
+
+    /// Write a bundle with the given content to `target`.
+    /// Returns the paths of all part files we wrote.
+    pub fn make(
+        target: BundleTarget,
+        content: &'a BundleContent,
+        options: BundleV1Options,
+    ) -> Result<Vec<PathBuf>> {
+        let mut bundle = BundleV1::new(target, content, options);
+
+        match bundle.target {
+            BundleTarget::Seekable(_) => {
+                bundle.seek(bundle.header_size())?;
+                let end = bundle.add_files(false)?;
+                bundle.index_start = if bundle.options.parts.is_some() {
+                    bundle.header_size()
+                } else {
+                    end
+                };
+                let index = bundle.make_index()?;
+                bundle.target.write_all(&index)?;
+                bundle.write_header()?;
+            }
+
+            // Split bundles don't keep files in the target,
+            // so the index comes right after the header.
+            BundleTarget::Stream(_) if bundle.options.parts.is_some() => {
+                bundle.add_files(false)?;
+                bundle.index_start = bundle.header_size();
+                let index = bundle.make_index()?;
+                bundle.write_header()?;
+                bundle.target.write_all(&index)?;
+            }
+
+            BundleTarget::Stream(_) => {
+                // Find where every file (and thus the index) will be
+                bundle.index_start = bundle.add_files(true)?;
+                let index = bundle.make_index()?;
+                let planned = std::mem::take(&mut bundle.filelist);
+
+                bundle.write_header()?;
+                bundle.add_files(false)?;
+
+                // Compression is deterministic, so this only fails
+                // if the content dir changed while we were packing.
+                for (a, b) in planned.iter().zip(&bundle.filelist) {
+                    if a.start != b.start || a.gzip_len != b.gzip_len {
+                        bail!("`{}` changed while packing", b.path.display());
+                    }
+                }
+
+                bundle.target.write_all(&index)?;
+            }
+        }
+
+        bundle.target.flush()?;
+
+        let parts = match &bundle.options.parts {
+            Some(opts) => bundle
+                .parts
+                .iter()
+                .map(|x| opts.path.with_file_name(&x.name))
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(parts)
+    }
+
+    /// The size of this bundle's header
+    fn header_size(&self) -> u64 {
+        self.options.header_size()
+    }
+
+    /// Seek to `pos` in our target, if it is seekable
+    fn seek(&mut self, pos: u64) -> Result<()> {
+        match &mut self.target {
+            BundleTarget::Seekable(x) => {
+                x.seek(std::io::SeekFrom::Start(pos))?;
+                Ok(())
+            }
+            BundleTarget::Stream(_) => bail!("can't seek in a stream"),
+        }
     }
 
-    fn new(target: Box<dyn WriteSeek>, build_dir: PathBuf) -> Result<BundleV1> {
-        Ok(BundleV1 {
+    fn new(target: BundleTarget, content: &'a BundleContent, options: BundleV1Options) -> Self {
+        BundleV1 {
             filelist: Vec::new(),
+            options,
             target,
-            content_dir: build_dir.join("content"),
+            parts: Vec::new(),
+            part_file: None,
+            content,
             index_start: 0,
             index_gzip_len: 0,
             index_real_len: 0,
-        })
+        }
     }
 
-    fn add_files(&mut self) -> Result<u64> {
-        let mut byte_count = HEADER_SIZE; // Start after header
-        let mut real_len_sum = 0; // Compute average compression ratio
+    /// Write the blobs of one prefetch group, or of one file that isn't in a group,
+    /// to the current part. Starts a new part if they don't all fit, since a group
+    /// must stay in one part to be fetched with one request.
+    fn write_part(&mut self, group: Option<usize>, blobs: &[(&str, Vec<u8>)]) -> Result<()> {
+        let opts = self.options.parts.as_ref().unwrap();
+        let len: u64 = blobs.iter().map(|x| x.1.len() as u64).sum();
+        if len > opts.max_size {
+            match group {
+                Some(g) => bail!(
+                    "prefetch group `{}` is {len} bytes after compression, which is larger than the part size ({})",
+                    self.options.prefetch[g].name,
+                    opts.max_size
+                ),
+                None => bail!(
+                    "`{}` is {len} bytes after compression, which is larger than the part size ({})",
+                    blobs[0].0,
+                    opts.max_size
+                ),
+            }
+        }
 
-        self.target.seek(std::io::SeekFrom::Start(byte_count))?;
+        let full = match self.parts.last() {
+            None => true,
+            Some(p) => p.len + len > opts.max_size,
+        };
+
+        if full {
+            let start = self.parts.last().map(|p| p.start + p.len).unwrap_or(0);
+            let name = format!(
+                "{}.{:03}",
+                opts.path.file_name().unwrap().to_str().unwrap(),
+                self.parts.len()
+            );
+            info!(tectonic_log_source = "pack", "Writing part `{name}`");
+            self.part_file = Some(File::create(opts.path.with_file_name(&name))?);
+            self.parts.push(PartEntry {
+                start,
+                len: 0,
+                name,
+            });
+        }
 
-        let filelist_file = File::open(self.content_dir.join("FILELIST"))?;
-        let reader = BufReader::new(filelist_file);
+        let part_file = self.part_file.as_mut().unwrap();
+        for (path, blob) in blobs {
+            part_file
+                .write_all(blob)
+                .with_context(|| format!("while writing `{path}`"))?;
+        }
+        self.parts.last_mut().unwrap().len += len;
+        Ok(())
+    }
 
-        info!(tectonic_log_source = "pack", "Building ttbv1 bundle...");
+    /// Compress all files and write them to the bundle.
+    /// If `dry_run` is true, only compute their positions.
+    /// Returns the position of the first byte after the last file.
+    fn add_files(&mut self, dry_run: bool) -> Result<u64> {
+        // Start after header.
+        // Split bundles keep files in parts, so start at zero.
+        let mut byte_count = if self.options.parts.is_some() {
+            0
+        } else {
+            self.header_size()
+        };
+        let mut real_len_sum = 0; // Compute average compression ratio
 
-        for line in reader.lines() {
-            stdout().flush()?;
+        let content = self.content;
+        let mut files: Vec<&BundleFile> = content.files.iter().collect();
 
-            let line = line?;
-            let mut bits = line.split_whitespace();
+        // Put files in prefetch group order, then layout order.
+        // This sort is stable, so all other files stay in FILELIST order.
+        let mut order: HashMap<&str, (usize, usize, usize)> = HashMap::new();
+        for (g, group) in self.options.prefetch.iter().enumerate() {
+            for (i, x) in group.members.iter().enumerate() {
+                order.entry(x.as_str()).or_insert((0, g, i));
+            }
+        }
+        for (i, x) in self.options.layout.iter().enumerate() {
+            order.entry(x.as_str()).or_insert((1, i, 0));
+        }
+        files.sort_by_key(|x| order.get(x.path.as_str()).copied().unwrap_or((2, 0, 0)));
+
+        // The prefetch group each file is stored with
+        let stored: HashMap<&str, usize> = files
+            .iter()
+            .filter_map(|x| match order.get(x.path.as_str()) {
+                Some((0, g, _)) => Some((x.path.as_str(), *g)),
+                _ => None,
+            })
+            .collect();
+
+        if dry_run {
+            info!(
+                tectonic_log_source = "pack",
+                "Planning ttbv1 bundle layout..."
+            );
+        } else {
+            info!(tectonic_log_source = "pack", "Building ttbv1 bundle...");
+        }
 
-            if let Some(hash) = bits.next() {
-                let path = bits.collect::<Vec<&str>>().join(" ");
+        // In split bundles, the blobs of the prefetch group we're writing
+        // are held until the group ends, so that we can keep it in one part.
+        let mut group_blobs: Vec<(&str, Vec<u8>)> = Vec::new();
+        let mut group = None;
+
+        for entry in content.entries_of(files) {
+            let mut entry = entry?;
+            let path = entry.path;
+            let file_group = stored.get(path).copied();
+
+            // Compress and write bytes
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            let real_len = std::io::copy(&mut entry.reader, &mut encoder)
+                .with_context(|| format!("while compressing `{path}`"))?;
+            let blob = encoder.finish()?;
+            let gzip_len = blob.len() as u64;
+
+            if !self.options.wide && real_len.max(gzip_len) >= u32::MAX as u64 {
+                bail!(
+                    "`{path}` is {real_len} bytes ({gzip_len} compressed), \
+                    which is too large for a normal ttbv1 bundle. \
+                    Use the `v1-wide` format to pack it."
+                );
+            }
 
-                let mut file = fs::File::open(&self.content_dir.join(&path))?;
+            if !dry_run {
+                if self.options.parts.is_some() {
+                    if !group_blobs.is_empty() && (file_group.is_none() || file_group != group) {
+                        self.write_part(group, &group_blobs)?;
+                        group_blobs.clear();
+                    }
+                    group = file_group;
+                    group_blobs.push((path, blob));
+                } else {
+                    self.target
+                        .write_all(&blob)
+                        .with_context(|| format!("while writing `{path}`"))?;
+                }
+            }
 
-                // Compress and write bytes
-                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-                let real_len = std::io::copy(&mut file, &mut encoder)?;
-                let gzip_len = self.target.write(&encoder.finish()?)?;
-                assert!(real_len < u32::MAX as u64);
-                assert!(gzip_len < u32::MAX as usize);
+            // Add to index
+            self.filelist.push(FileListEntry {
+                start: byte_count,
+                gzip_len,
+                real_len,
+                path: PathBuf::from(path),
+                hash: entry.hash.map(|x| x.to_owned()),
+            });
+            byte_count += gzip_len;
+            real_len_sum += real_len;
+        }
 
-                // Add to index
-                self.filelist.push(FileListEntry {
-                    start: byte_count,
-                    gzip_len: gzip_len as u32,
-                    real_len: real_len as u32,
-                    path: PathBuf::from(path),
-                    hash: hash.to_owned(),
-                });
-                byte_count += gzip_len as u64;
-                real_len_sum += real_len;
-            } else {
-                bail!("malformed filelist line");
-            }
+        if !group_blobs.is_empty() {
+            self.write_part(group, &group_blobs)?;
         }
 
-        info!(
-            tectonic_log_source = "pack",
-            "Average compression ratio: {:.2}",
-            real_len_sum as f64 / byte_count as f64
-        );
+        if !dry_run {
+            info!(
+                tectonic_log_source = "pack",
+                "Average compression ratio: {:.2}",
+                real_len_sum as f64 / byte_count as f64
+            );
+        }
 
         Ok(byte_count)
     }
 
-    fn write_index(&mut self) -> Result<()> {
-        // Generate a ttbv1 index and write it to the bundle.
+    fn make_index(&mut self) -> Result<Vec<u8>> {
+        // Generate a compressed ttbv1 index.
         //
         // This index is a replacement for FILELIST and SEARCH, containing everything in those files
         // (in addition to some ttbv1-specific information)
         //
         // The original FILELIST and SEARCH files are still included in the bundle.
 
-        // Get current position
-        self.index_start = self.target.stream_position()?;
-
         info!(tectonic_log_source = "pack", "Writing index");
 
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-        let mut real_len = 0usize;
+        let mut index = String::new();
 
-        real_len += encoder.write("[DEFAULTSEARCH]\n".as_bytes())?;
-        real_len += encoder.write("MAIN\n".as_bytes())?;
+        index += "[META]\n";
+        for (k, v) in &self.content.meta {
+            index += &format!("{k}={v}\n");
+        }
 
-        real_len += encoder.write("[SEARCH:MAIN]\n".as_bytes())?;
-        for l in fs::read_to_string(self.content_dir.join("SEARCH"))?.lines() {
-            real_len += encoder.write(l.as_bytes())?;
-            real_len += encoder.write(b"\n")?;
+        if let Some((name, _)) = self.content.search.first() {
+            index += "[DEFAULTSEARCH]\n";
+            index += &format!("{name}\n");
         }
 
-        real_len += encoder.write("[FILELIST]\n".as_bytes())?;
-        for i in &self.filelist {
-            let s = format!("{i}\n");
-            real_len += encoder.write(s.as_bytes())?;
+        for (name, rules) in &self.content.search {
+            index += &format!("[SEARCH:{name}]\n");
+            for l in rules {
+                index += l;
+                index += "\n";
+            }
         }
-        let gzip_len = self.target.write(&encoder.finish()?)?;
-        assert!(gzip_len < u32::MAX as usize);
-        assert!(real_len < u32::MAX as usize);
-        self.index_gzip_len = gzip_len as u32;
-        self.index_real_len = real_len as u32;
+
+        // Each prefetch group gets the byte range of the files stored with it,
+        // followed by all its members.
+        let entries: HashMap<&str, &FileListEntry> = self
+            .filelist
+            .iter()
+            .map(|x| (x.path.to_str().unwrap(), x))
+            .collect();
+        let mut stored: HashMap<&str, usize> = HashMap::new();
+        for (g, group) in self.options.prefetch.iter().enumerate() {
+            for x in &group.members {
+                stored.entry(x.as_str()).or_insert(g);
+            }
+        }
+        for (g, group) in self.options.prefetch.iter().enumerate() {
+            let own: Vec<&&FileListEntry> = group
+                .members
+                .iter()
+                .filter(|x| stored[x.as_str()] == g)
+                .filter_map(|x| entries.get(x.as_str()))
+                .collect();
+            let start = own.iter().map(|x| x.start).min().unwrap_or(0);
+            let end = own.iter().map(|x| x.start + x.gzip_len).max().unwrap_or(0);
+
+            index += &format!("[PREFETCH:{}]\n", group.name);
+            index += &format!("{start} {}\n", end - start);
+            for x in &group.members {
+                index += x;
+                index += "\n";
+            }
+        }
+
+        // The index lists files by path, no matter how they are laid out.
+        let mut filelist = Vec::from_iter(&self.filelist);
+        filelist.sort_by(|a, b| a.path.cmp(&b.path));
+
+        if self.options.parts.is_some() {
+            index += "[PARTS]\n";
+            for p in &self.parts {
+                index += &format!("{} {} {}\n", p.start, p.len, p.name);
+            }
+        }
+
+        index += "[FILELIST]\n";
+        for i in filelist {
+            index += &format!("{i}\n");
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(index.as_bytes())?;
+        let real_len = index.len() as u64;
+        let index = encoder.finish()?;
+        let gzip_len = index.len() as u64;
+
+        if !self.options.wide && real_len.max(gzip_len) >= u32::MAX as u64 {
+            bail!(
+                "this bundle's index is {real_len} bytes ({gzip_len} compressed), \
+                which is too large for a normal ttbv1 bundle. \
+                Use the `v1-wide` format to pack it."
+            );
+        }
+        self.index_gzip_len = gzip_len;
+        self.index_real_len = real_len;
 
         info!(
             tectonic_log_source = "pack",
             "index is at {} and has length {}", self.index_start, self.index_gzip_len
         );
 
-        Ok(())
+        Ok(index)
     }
 
     fn write_header(&mut self) -> Result<u64> {
-        self.target.seek(std::io::SeekFrom::Start(0))?;
+        if let BundleTarget::Seekable(_) = self.target {
+            self.seek(0)?;
+        }
 
         info!(tectonic_log_source = "pack", "Writing header");
 
         // Parse bundle hash
-        let mut hash_file = File::open(self.content_dir.join("SHA256SUM")).unwrap();
-        let mut hash_text = String::new();
-        hash_file.read_to_string(&mut hash_text)?;
-        let digest = decode_hex(hash_text.trim())?;
+        let digest = hex::decode(&self.content.hash).context("bad bundle hash")?;
+        if digest.len() != 32 {
+            bail!(
+                "bad bundle hash `{}`: expected 32 bytes, got {}",
+                self.content.hash,
+                digest.len()
+            );
+        }
 
-        let mut byte_count = 0u64;
+        let mut header = Vec::new();
 
         // 14 bytes: signature
         // Always "tectonicbundle", in any bundle version.
         //
         // This "magic sequence" lets us more easily distinguish between
         // random binary files and proper tectonic bundles.
-        byte_count += self.target.write(b"tectonicbundle")? as u64;
+        header.extend_from_slice(b"tectonicbundle");
 
         // 4 bytes: bundle version
-        byte_count += self.target.write(&1u32.to_le_bytes())? as u64;
+        header.extend_from_slice(&self.options.version().to_le_bytes());
 
         // 8 + 4 + 4 = 12 bytes: location and real length of index
-        byte_count += self.target.write(&self.index_start.to_le_bytes())? as u64;
-        byte_count += self.target.write(&self.index_gzip_len.to_le_bytes())? as u64;
-        byte_count += self.target.write(&self.index_real_len.to_le_bytes())? as u64;
+        // (8 + 8 + 8 = 24 bytes in wide bundles)
+        header.extend_from_slice(&self.index_start.to_le_bytes());
+        if self.options.wide {
+            header.extend_from_slice(&self.index_gzip_len.to_le_bytes());
+            header.extend_from_slice(&self.index_real_len.to_le_bytes());
+        } else {
+            header.extend_from_slice(&(self.index_gzip_len as u32).to_le_bytes());
+            header.extend_from_slice(&(self.index_real_len as u32).to_le_bytes());
+        }
 
         // 32 bytes: bundle hash
         // We include this in the header so we don't need to load the index to get the hash.
-        byte_count += self.target.write(&digest)? as u64;
+        header.extend_from_slice(&digest);
 
         // Make sure we wrote the expected number of bytes
-        assert!(byte_count == HEADER_SIZE);
+        if header.len() as u64 != self.header_size() {
+            bail!(
+                "wrote a header of {} bytes, expected {}",
+                header.len(),
+                self.header_size()
+            );
+        }
+
+        self.target.write_all(&header)?;
+        let byte_count = header.len() as u64;
 
         Ok(byte_count)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(parts: bool, wide: bool) -> BundleV1Options {
+        BundleV1Options {
+            prefetch: Vec::new(),
+            layout: Vec::new(),
+            parts: parts.then(|| PartOptions {
+                max_size: 1 << 20,
+                path: PathBuf::from("bundle.ttb"),
+            }),
+            wide,
+        }
+    }
+
+    #[test]
+    fn header_version() {
+        assert_eq!(options(false, false).version(), 1);
+        assert_eq!(options(true, false).version(), 2);
+        assert_eq!(options(false, true).version(), 3);
+        assert_eq!(options(true, true).version(), 3);
+    }
+
+    #[test]
+    fn header_size() {
+        assert_eq!(options(false, false).header_size(), HEADER_SIZE);
+        assert_eq!(options(true, false).header_size(), HEADER_SIZE);
+        assert_eq!(options(false, true).header_size(), WIDE_HEADER_SIZE);
+        assert_eq!(options(true, true).header_size(), WIDE_HEADER_SIZE);
+    }
+}
@@ -0,0 +1,61 @@
+use super::{
+    flat_files,
+    writer::{BundleContent, BundleWriter, PackOptions},
+};
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    io::{Seek, Write},
+    path::PathBuf,
+};
+use tracing::info;
+use zip::{write::FileOptions, CompressionMethod, DateTime, ZipWriter};
+
+/// A legacy zip bundle, as read by older Tectonic releases.
+/// This is a flat zip file: every file is stored at the top level, by name.
+pub struct BundleZip {
+    target: PathBuf,
+}
+
+impl BundleWriter for BundleZip {
+    fn write(&mut self, content: &BundleContent) -> Result<Vec<PathBuf>> {
+        Self::make(File::create(&self.target)?, content)?;
+        Ok(vec![self.target.clone()])
+    }
+}
+
+impl BundleZip {
+    /// Make a writer for zip bundles
+    pub fn writer(options: &PackOptions) -> Result<Box<dyn BundleWriter>> {
+        Ok(Box::new(Self {
+            target: options.simple_target("zip")?.to_path_buf(),
+        }))
+    }
+
+    pub fn make(target: impl Write + Seek, content: &BundleContent) -> Result<()> {
+        let files = flat_files(content);
+
+        info!(
+            tectonic_log_source = "pack",
+            "Building zip bundle with {} files...",
+            files.len()
+        );
+
+        // Use a fixed timestamp, so that zip bundles are reproducible
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(DateTime::default())
+            .unix_permissions(0o644);
+
+        let mut zip = ZipWriter::new(target);
+        for (name, path) in files {
+            zip.start_file(&name, options)?;
+            let mut file = content.open(&path)?;
+            std::io::copy(&mut file, &mut zip)
+                .with_context(|| format!("while writing `{path}`"))?;
+        }
+        zip.finish()?;
+
+        Ok(())
+    }
+}
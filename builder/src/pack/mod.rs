@@ -1 +1,264 @@
+use crate::{
+    provenance::{build_timestamp, format_timestamp},
+    search::SearchIndex,
+    select::{input::InputRegistry, spec::BundleSpec},
+    trace::load_trace,
+};
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tracing::{info, warn};
+use writer::BundleContent;
+
+pub mod bundledir;
+pub mod bundleitar;
 pub mod bundlev1;
+pub mod bundlezip;
+pub mod writer;
+
+/// A named group of files to store contiguously
+#[derive(Debug, Clone)]
+pub struct PrefetchGroup {
+    pub name: String,
+
+    /// Paths of the files in this group, in the order they were listed
+    pub members: Vec<String>,
+}
+
+/// Make the metadata we embed in a bundle.
+/// Returns a list of `(key, value)` pairs, keys may be repeated.
+/// Inputs are described using the default input registry.
+pub fn make_meta(spec: &BundleSpec, bundle_dir: &Path) -> Result<Vec<(String, String)>> {
+    let registry = InputRegistry::default();
+
+    let mut meta = vec![
+        ("name".to_owned(), spec.bundle.name.clone()),
+        (
+            "builder".to_owned(),
+            format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        ),
+        ("created".to_owned(), format_timestamp(build_timestamp()?)),
+    ];
+
+    let mut inputs = Vec::from_iter(&spec.inputs);
+    inputs.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, input) in inputs {
+        meta.push((
+            "input".to_owned(),
+            format!("{name} {}", registry.describe(&input.source(), bundle_dir)),
+        ));
+    }
+
+    for (k, v) in spec.bundle.meta.iter().flatten() {
+        meta.push((k.clone(), v.clone()));
+    }
+
+    Ok(meta)
+}
+
+/// Read the paths in `content/FILELIST`
+pub fn read_filelist_paths(content_dir: &Path) -> Result<Vec<String>> {
+    Ok(fs::read_to_string(content_dir.join("FILELIST"))?
+        .lines()
+        .filter_map(|x| x.split_once(' ').map(|(_, path)| path.to_owned()))
+        .collect())
+}
+
+/// Make a search index from `content/FILELIST` and `content/SEARCH`
+pub fn content_search_index(content_dir: &Path) -> Result<SearchIndex> {
+    let paths = read_filelist_paths(content_dir)?;
+    let rules: Vec<String> = fs::read_to_string(content_dir.join("SEARCH"))?
+        .lines()
+        .map(|x| x.to_owned())
+        .collect();
+    Ok(SearchIndex::new(paths.iter().map(|x| x.as_str()), &rules))
+}
+
+/// Compute a blob layout from access traces.
+///
+/// Files that appear in more traces come first, so that files
+/// commonly loaded together end up next to each other. Ties are
+/// broken by the first trace a file appears in, and then by the
+/// order it was opened in that trace.
+pub fn trace_layout(content_dir: &Path, traces: &[PathBuf]) -> Result<Vec<String>> {
+    let search = content_search_index(content_dir)?;
+
+    // Map of path to (trace count, first trace, position in first trace, last trace)
+    let mut seen: HashMap<String, (usize, usize, usize, usize)> = HashMap::new();
+    for (t, trace) in traces.iter().enumerate() {
+        for (i, name) in load_trace(trace)?.iter().enumerate() {
+            if let Some(path) = search.resolve(name) {
+                seen.entry(path.to_owned())
+                    .and_modify(|x| {
+                        // Files may appear in one trace under many names,
+                        // so only count each trace once.
+                        if x.3 != t {
+                            x.0 += 1;
+                            x.3 = t;
+                        }
+                    })
+                    .or_insert((1, t, i, t));
+            }
+        }
+    }
+
+    let mut layout = Vec::from_iter(seen);
+    layout.sort_by_key(|(_, (count, trace, pos, _))| (usize::MAX - count, *trace, *pos));
+
+    info!(
+        tectonic_log_source = "pack",
+        "placing {} traced files first",
+        layout.len()
+    );
+
+    Ok(layout.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Find the files in each of the prefetch groups in `spec`.
+/// Groups are returned in name order.
+pub fn prefetch_groups(
+    spec: &BundleSpec,
+    bundle_dir: &Path,
+    content_dir: &Path,
+) -> Result<Vec<PrefetchGroup>> {
+    let prefetch = match &spec.bundle.prefetch {
+        Some(x) if !x.is_empty() => x,
+        _ => return Ok(Vec::new()),
+    };
+    let search = content_search_index(content_dir)?;
+
+    let mut groups = Vec::new();
+    for (name, group) in prefetch {
+        let mut names = group.files.clone().unwrap_or_default();
+        if let Some(trace) = &group.trace {
+            names.extend(load_trace(&bundle_dir.join(trace))?);
+        }
+
+        let mut members: Vec<String> = Vec::new();
+        for n in names {
+            match search.resolve(&n) {
+                Some(path) => {
+                    if !members.iter().any(|x| x == path) {
+                        members.push(path.to_owned())
+                    }
+                }
+                None => warn!(
+                    tectonic_log_source = "pack",
+                    "prefetch group `{name}` contains `{n}`, which isn't in this bundle"
+                ),
+            }
+        }
+
+        info!(
+            tectonic_log_source = "pack",
+            "prefetch group `{name}` has {} files",
+            members.len()
+        );
+
+        groups.push(PrefetchGroup {
+            name: name.clone(),
+            members,
+        });
+    }
+
+    Ok(groups)
+}
+
+/// Find the part files of the bundle at `target`
+/// (`<target>.000`, `<target>.001`, ...), sorted by name.
+pub fn part_files(target: &Path) -> Result<Vec<PathBuf>> {
+    let name = target.file_name().unwrap().to_str().unwrap();
+    let dir = target.parent().unwrap();
+
+    let mut parts = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let is_part = file_name
+            .to_str()
+            .and_then(|x| x.strip_prefix(name))
+            .and_then(|x| x.strip_prefix('.'))
+            .map(|x| x.len() == 3 && x.bytes().all(|b| b.is_ascii_digit()))
+            .unwrap_or(false);
+        if is_part && entry.file_type()?.is_file() {
+            parts.push(entry.path());
+        }
+    }
+
+    parts.sort();
+    Ok(parts)
+}
+
+/// Find the files that go in a flat bundle, where every file
+/// is stored by name. Returns `(name, path)` pairs sorted by name.
+///
+/// Each name gets the file Tectonic would find with a v1 bundle's
+/// default search order. Names that don't resolve to one file are left out.
+pub fn flat_files(content: &BundleContent) -> Vec<(String, String)> {
+    let search = SearchIndex::new(
+        content.files.iter().map(|x| x.path.as_str()),
+        content.default_search(),
+    );
+
+    let mut files = Vec::new();
+    let mut skipped = 0usize;
+    for name in search.names() {
+        match search.resolve(name) {
+            Some(path) => files.push((name.to_owned(), path.to_owned())),
+            None => skipped += 1,
+        }
+    }
+    files.sort();
+
+    if skipped != 0 {
+        warn!(
+            tectonic_log_source = "pack",
+            "{skipped} file names aren't in the search path and have many candidates, leaving them out of this flat bundle"
+        );
+    }
+
+    files
+}
+
+/// False once we've found that the filesystem we're writing to
+/// doesn't support reflinks, so we don't keep trying.
+static TRY_REFLINK: AtomicBool = AtomicBool::new(true);
+
+/// Reflink `src` to `dst`, or hardlink it if we can't, or copy it if we can't do either.
+/// Reflinks are preferred, since changing a reflinked file doesn't change the content dir.
+/// Both fail across filesystems, for example.
+/// Returns true if we made a copy.
+pub fn link_or_copy(src: &Path, dst: &Path) -> Result<bool> {
+    if TRY_REFLINK.load(Ordering::Relaxed) {
+        match reflink_copy::reflink(src, dst) {
+            Ok(()) => return Ok(false),
+
+            // These mean that reflinks won't work between these two directories
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::Unsupported
+                        | io::ErrorKind::CrossesDevices
+                        | io::ErrorKind::InvalidInput
+                ) =>
+            {
+                TRY_REFLINK.store(false, Ordering::Relaxed)
+            }
+
+            Err(e) => {
+                return Err(e).with_context(|| format!("while reflinking `{}`", src.display()))
+            }
+        }
+    }
+
+    if fs::hard_link(src, dst).is_ok() {
+        return Ok(false);
+    }
+
+    fs::copy(src, dst).with_context(|| format!("while copying `{}`", src.display()))?;
+    Ok(true)
+}
@@ -0,0 +1,342 @@
+//! Bundle writers.
+//!
+//! A [`BundleWriter`] turns a bundle's [`BundleContent`] (its files, search
+//! profiles, and metadata) into a bundle in some format. Writers are picked
+//! by name from a [`WriterRegistry`], so new formats may be added without
+//! changing the `pack` job.
+
+use super::{
+    bundledir::BundleDir, bundleitar::BundleItar, bundlev1::BundleV1, bundlezip::BundleZip,
+    PrefetchGroup,
+};
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+/// A file in a bundle
+#[derive(Debug, Clone)]
+pub struct BundleFile {
+    /// Path relative to the content dir (does not start with a slash)
+    pub path: String,
+
+    /// Hex sha256 of this file, or `None` for special files that aren't hashed
+    pub hash: Option<String>,
+}
+
+/// A file to write to a bundle, with a reader for its data
+pub struct BundleEntry<'a> {
+    pub path: &'a str,
+    pub hash: Option<&'a str>,
+    pub reader: Box<dyn Read + 'a>,
+}
+
+/// Where a bundle's file data is kept
+enum ContentStore {
+    /// A content dir, made by the `select` job
+    Dir(PathBuf),
+
+    /// Files in memory, by path
+    Memory(HashMap<String, Vec<u8>>),
+}
+
+/// Everything a writer needs to make a bundle.
+pub struct BundleContent {
+    /// The bundle hash, in hex
+    pub hash: String,
+
+    /// Metadata for this bundle. Keys may be repeated.
+    pub meta: Vec<(String, String)>,
+
+    /// Search profiles as `(name, rules)` pairs.
+    /// The first profile is the default.
+    pub search: Vec<(String, Vec<String>)>,
+
+    /// Every file in this bundle, in FILELIST order
+    pub files: Vec<BundleFile>,
+
+    store: ContentStore,
+}
+
+impl BundleContent {
+    /// Load a bundle from a content dir made by the `select` job.
+    /// The bundle's metadata is left empty.
+    pub fn load(content_dir: &Path) -> Result<Self> {
+        let hash = fs::read_to_string(content_dir.join("SHA256SUM"))
+            .context("while reading `content/SHA256SUM`")?
+            .trim()
+            .to_owned();
+
+        let search = fs::read_to_string(content_dir.join("SEARCH"))
+            .context("while reading `content/SEARCH`")?
+            .lines()
+            .map(|x| x.to_owned())
+            .collect();
+
+        let mut files = Vec::new();
+        for line in fs::read_to_string(content_dir.join("FILELIST"))
+            .context("while reading `content/FILELIST`")?
+            .lines()
+        {
+            let (hash, path) = match line.split_once(' ') {
+                Some(x) => x,
+                None => bail!("malformed filelist line `{line}`"),
+            };
+            files.push(BundleFile {
+                path: path.to_owned(),
+                hash: (hash != "nohash").then(|| hash.to_owned()),
+            });
+        }
+
+        Ok(Self {
+            hash,
+            meta: Vec::new(),
+            search: vec![("MAIN".to_owned(), search)],
+            files,
+            store: ContentStore::Dir(content_dir.to_path_buf()),
+        })
+    }
+
+    /// Make a bundle from files in memory, given as `(path, data)` pairs.
+    /// Files are sorted by path and aren't hashed.
+    /// This is mostly useful for testing writers.
+    pub fn from_memory(
+        hash: String,
+        search: Vec<(String, Vec<String>)>,
+        files: Vec<(String, Vec<u8>)>,
+    ) -> Self {
+        let mut list: Vec<BundleFile> = files
+            .iter()
+            .map(|(path, _)| BundleFile {
+                path: path.clone(),
+                hash: None,
+            })
+            .collect();
+        list.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Self {
+            hash,
+            meta: Vec::new(),
+            search,
+            files: list,
+            store: ContentStore::Memory(files.into_iter().collect()),
+        }
+    }
+
+    /// The search rules of this bundle's default profile
+    pub fn default_search(&self) -> &[String] {
+        self.search
+            .first()
+            .map(|(_, rules)| rules.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// If this file is on disk, return its path
+    pub fn file_path(&self, path: &str) -> Option<PathBuf> {
+        match &self.store {
+            ContentStore::Dir(dir) => Some(dir.join(path)),
+            ContentStore::Memory(_) => None,
+        }
+    }
+
+    /// Open the file at `path`
+    pub fn open(&self, path: &str) -> Result<Box<dyn Read + '_>> {
+        match &self.store {
+            ContentStore::Dir(dir) => Ok(Box::new(
+                File::open(dir.join(path)).with_context(|| format!("while opening `{path}`"))?,
+            )),
+            ContentStore::Memory(files) => match files.get(path) {
+                Some(x) => Ok(Box::new(Cursor::new(x.as_slice()))),
+                None => bail!("`{path}` is not in this bundle"),
+            },
+        }
+    }
+
+    /// The size of the file at `path`, in bytes
+    pub fn size(&self, path: &str) -> Result<u64> {
+        match &self.store {
+            ContentStore::Dir(dir) => Ok(fs::metadata(dir.join(path))
+                .with_context(|| format!("while opening `{path}`"))?
+                .len()),
+            ContentStore::Memory(files) => match files.get(path) {
+                Some(x) => Ok(x.len() as u64),
+                None => bail!("`{path}` is not in this bundle"),
+            },
+        }
+    }
+
+    /// Iterate over every file in this bundle, in FILELIST order
+    pub fn entries(&self) -> impl Iterator<Item = Result<BundleEntry<'_>>> {
+        self.entries_of(&self.files)
+    }
+
+    /// Iterate over the given files, in order
+    pub fn entries_of<'a>(
+        &'a self,
+        files: impl IntoIterator<Item = &'a BundleFile> + 'a,
+    ) -> impl Iterator<Item = Result<BundleEntry<'a>>> + 'a {
+        files.into_iter().map(|x| {
+            Ok(BundleEntry {
+                path: &x.path,
+                hash: x.hash.as_deref(),
+                reader: self.open(&x.path)?,
+            })
+        })
+    }
+}
+
+/// Options for the `pack` job, shared by all writers.
+/// Writers ignore options that don't apply to their format.
+pub struct PackOptions {
+    /// Where to write the bundle.
+    /// Parts of split bundles are written next to this path.
+    pub target: PathBuf,
+
+    /// If true, write the bundle to stdout instead of `target`
+    pub stdout: bool,
+
+    /// Groups of files to store contiguously
+    pub prefetch: Vec<PrefetchGroup>,
+
+    /// Paths of files to write first, in this order
+    pub layout: Vec<String>,
+
+    /// If set, split this bundle into parts of at most this many bytes
+    pub part_size: Option<u64>,
+}
+
+impl PackOptions {
+    /// Check these options for a format that only supports plain files,
+    /// and return the target path.
+    pub fn simple_target(&self, format: &str) -> Result<&Path> {
+        if self.part_size.is_some() {
+            bail!("`{format}` bundles can't be split into parts");
+        }
+        if !self.layout.is_empty() {
+            warn!(
+                tectonic_log_source = "pack",
+                "traces don't change the layout of `{format}` bundles, ignoring them"
+            );
+        }
+        if self.stdout {
+            bail!("`{format}` bundles can't be written to stdout");
+        }
+        Ok(&self.target)
+    }
+}
+
+/// Something that writes bundles in some format.
+pub trait BundleWriter {
+    /// Write a bundle with the given content.
+    /// Returns the paths of all files we wrote.
+    fn write(&mut self, content: &BundleContent) -> Result<Vec<PathBuf>>;
+}
+
+/// A function that makes a writer
+pub type WriterConstructor = fn(&PackOptions) -> Result<Box<dyn BundleWriter>>;
+
+/// A bundle format we can write
+#[derive(Clone, Copy)]
+pub struct WriterFormat {
+    /// A one-line description of this format
+    pub description: &'static str,
+
+    /// The extension of bundles in this format.
+    /// `None` means this format makes a directory.
+    pub extension: Option<&'static str>,
+
+    pub constructor: WriterConstructor,
+}
+
+/// Bundle formats, by name
+pub struct WriterRegistry {
+    formats: BTreeMap<String, WriterFormat>,
+}
+
+impl Default for WriterRegistry {
+    /// Make a registry with all formats this crate provides
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(
+            "v1",
+            WriterFormat {
+                description: "a ttbv1 bundle",
+                extension: Some("ttb"),
+                constructor: |x| BundleV1::writer(x, false),
+            },
+        );
+        registry.register(
+            "v1-wide",
+            WriterFormat {
+                description:
+                    "a ttbv1 bundle with 64-bit index lengths, for files larger than 4 GiB",
+                extension: Some("ttb"),
+                constructor: |x| BundleV1::writer(x, true),
+            },
+        );
+        registry.register(
+            "zip",
+            WriterFormat {
+                description: "a flat zip bundle, for older Tectonic releases",
+                extension: Some("zip"),
+                constructor: BundleZip::writer,
+            },
+        );
+        registry.register(
+            "itar",
+            WriterFormat {
+                description:
+                    "a flat indexed tar bundle and its `.index.gz`, for older Tectonic releases",
+                extension: Some("tar"),
+                constructor: BundleItar::writer,
+            },
+        );
+        registry.register(
+            "dir",
+            WriterFormat {
+                description: "a flat directory bundle, for local use with Tectonic",
+                extension: None,
+                constructor: BundleDir::writer,
+            },
+        );
+        registry
+    }
+}
+
+impl WriterRegistry {
+    /// Make a registry with no formats
+    pub fn empty() -> Self {
+        Self {
+            formats: BTreeMap::new(),
+        }
+    }
+
+    /// Add a format, replacing any format with the same name
+    pub fn register(&mut self, name: &str, format: WriterFormat) {
+        self.formats.insert(name.to_owned(), format);
+    }
+
+    /// Iterate over all formats, sorted by name
+    pub fn formats(&self) -> impl Iterator<Item = (&str, &WriterFormat)> {
+        self.formats.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Find a format by name
+    pub fn get(&self, name: &str) -> Result<&WriterFormat> {
+        match self.formats.get(name) {
+            Some(x) => Ok(x),
+            None => bail!(
+                "unknown bundle format `{name}`, expected one of {}",
+                self.formats
+                    .keys()
+                    .map(|x| format!("`{x}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
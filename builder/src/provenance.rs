@@ -0,0 +1,228 @@
+use crate::hex;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the embedded provenance file in a bundle's content dir
+pub const PROVENANCE_FILE: &str = "PROVENANCE.json";
+
+/// A patch that was applied to an input
+#[derive(Debug, Clone)]
+pub struct PatchRecord {
+    /// The diff file's path, relative to the bundle dir
+    pub diff: String,
+
+    /// The patched file's path, relative to the input's root
+    pub target: String,
+
+    /// sha256 of the diff file
+    pub hash: String,
+}
+
+/// A file we added to the bundle from an input
+#[derive(Debug, Clone)]
+pub struct FileRecord {
+    /// This file's path, relative to the input's root
+    pub path: String,
+
+    /// sha256 of this file, after patching
+    pub hash: String,
+
+    /// This file's license, if we know it
+    pub license: Option<String>,
+}
+
+/// Everything we know about where an input came from
+#[derive(Debug, Clone)]
+pub struct InputRecord {
+    /// This input's name in the bundle specification
+    pub name: String,
+
+    /// A short description of this input's source
+    pub source: String,
+
+    /// This input's hash, if it has one
+    pub hash: Option<String>,
+
+    /// All patches we applied to this input's files
+    pub patches: Vec<PatchRecord>,
+
+    /// Every file we added from this input
+    pub files: Vec<FileRecord>,
+}
+
+/// A record of how a bundle was made, written as a CycloneDX SBOM.
+pub struct Provenance<'a> {
+    /// The bundle's name
+    pub bundle_name: &'a str,
+
+    /// sha256 of the bundle specification file
+    pub spec_hash: &'a str,
+
+    /// The bundle hash, from `content/SHA256SUM`
+    pub bundle_hash: &'a str,
+
+    pub inputs: &'a [InputRecord],
+}
+
+impl Provenance<'_> {
+    /// Build a CycloneDX 1.5 document from this record
+    pub fn to_cyclonedx(&self) -> Result<Value> {
+        let components: Vec<Value> = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let mut files = Vec::from_iter(&input.files);
+                files.sort_by(|a, b| a.path.cmp(&b.path));
+                let files = files.into_iter().map(|f| {
+                    let mut c = json!({
+                        "type": "file",
+                        "bom-ref": format!("file:{}:{}", input.name, f.path),
+                        "name": f.path,
+                        "hashes": [{ "alg": "SHA-256", "content": f.hash }],
+                    });
+                    if let Some(license) = &f.license {
+                        c["licenses"] = json!([{ "license": { "name": license } }]);
+                    }
+                    c
+                });
+
+                let mut c = json!({
+                    "type": "file",
+                    "bom-ref": format!("input:{}", input.name),
+                    "name": input.name,
+                    "description": input.source,
+                    "components": input.patches.iter().map(|p| json!({
+                        "type": "file",
+                        "bom-ref": format!("patch:{}:{}", input.name, p.diff),
+                        "name": p.diff,
+                        "hashes": [{ "alg": "SHA-256", "content": p.hash }],
+                        "properties": [{ "name": "tectonic:patch-target", "value": p.target }],
+                    })).chain(files).collect::<Vec<_>>(),
+                });
+                if let Some(hash) = &input.hash {
+                    // Git commits are named by sha1, or sha256 in newer repositories
+                    let alg = if hash.len() == 40 { "SHA-1" } else { "SHA-256" };
+                    c["hashes"] = json!([{ "alg": alg, "content": hash }]);
+                }
+                c
+            })
+            .collect();
+
+        Ok(json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "metadata": {
+                "timestamp": format_timestamp(build_timestamp()?),
+                "tools": {
+                    "components": [{
+                        "type": "application",
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    }],
+                },
+                "component": {
+                    "type": "data",
+                    "bom-ref": "bundle",
+                    "name": self.bundle_name,
+                    "hashes": [{ "alg": "SHA-256", "content": self.bundle_hash }],
+                    "properties": [{ "name": "tectonic:spec-sha256", "value": self.spec_hash }],
+                },
+            },
+            "components": components,
+            "dependencies": [{
+                "ref": "bundle",
+                "dependsOn": self.inputs.iter().map(|x| format!("input:{}", x.name)).collect::<Vec<_>>(),
+            }],
+        }))
+    }
+
+    /// Write this record to `path`
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("while creating `{}`", path.display()))?;
+        serde_json::to_writer_pretty(file, &self.to_cyclonedx()?)?;
+        Ok(())
+    }
+}
+
+/// Add a packed bundle file to the provenance document at `path`.
+pub fn add_artifact(path: &Path, artifact: &Path, format: &str) -> Result<()> {
+    let mut doc: Value = serde_json::from_str(
+        &fs::read_to_string(path).with_context(|| format!("while reading `{}`", path.display()))?,
+    )?;
+
+    let name = artifact.file_name().unwrap().to_str().unwrap();
+    let component = json!({
+        "type": "file",
+        "bom-ref": format!("artifact:{name}"),
+        "name": name,
+        "hashes": [{ "alg": "SHA-256", "content": hash_file(artifact)? }],
+        "properties": [{ "name": "tectonic:format", "value": format }],
+    });
+
+    match doc["components"].as_array_mut() {
+        Some(c) => {
+            c.retain(|x| x["bom-ref"] != component["bom-ref"]);
+            c.push(component);
+        }
+        None => doc["components"] = json!([component]),
+    }
+
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(file, &doc)?;
+    Ok(())
+}
+
+/// Compute the sha256 of a file as a hex string
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let _ = std::io::copy(
+        &mut File::open(path)
+            .with_context(|| format!("while computing hash of `{}`", path.display()))?,
+        &mut hasher,
+    )?;
+    Ok(hex::encode(&hasher.finalize()))
+}
+
+/// Get the time this build happened, in seconds since the unix epoch.
+/// If `SOURCE_DATE_EPOCH` is set, we use it instead of the current time
+/// so that builds are reproducible.
+pub fn build_timestamp() -> Result<u64> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(s) => s.trim().parse().context("while parsing SOURCE_DATE_EPOCH"),
+        Err(_) => Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()),
+    }
+}
+
+/// Format a unix timestamp as an RFC 3339 UTC string,
+/// like `2023-03-13T00:00:00Z`.
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Convert days since epoch to a civil date.
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+
+    format!(
+        "{y:04}-{m:02}-{d:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
@@ -0,0 +1,121 @@
+use super::bundlev1::{BundleV1Header, BundleV1Index, BundleV1Reader, IndexEntry};
+use crate::search::SearchIndex;
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read, Seek},
+    path::Path,
+};
+
+/// A ttbv1 bundle, opened for reading.
+///
+/// This finds files by name the same way Tectonic does,
+/// using one of the bundle's search profiles.
+pub struct Bundle<R: Read + Seek> {
+    reader: BundleV1Reader<R>,
+
+    /// Index entries, by path
+    entries: HashMap<String, IndexEntry>,
+
+    /// Search indices, by profile name.
+    /// These are built when they are first used.
+    search: HashMap<String, SearchIndex>,
+}
+
+impl Bundle<BufReader<File>> {
+    /// Open the bundle at `path`.
+    /// Parts of split bundles are found next to this file.
+    pub fn open_path(path: &Path) -> Result<Self> {
+        Ok(Self::from_reader(BundleV1Reader::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Bundle<R> {
+    /// Read a bundle from `reader`.
+    /// Split bundles can't be read this way, use [`Bundle::open_path`].
+    pub fn new(reader: R) -> Result<Self> {
+        Ok(Self::from_reader(BundleV1Reader::new(reader)?))
+    }
+
+    fn from_reader(reader: BundleV1Reader<R>) -> Self {
+        let entries = reader
+            .index
+            .filelist
+            .iter()
+            .map(|x| (x.path.clone(), x.clone()))
+            .collect();
+
+        Self {
+            reader,
+            entries,
+            search: HashMap::new(),
+        }
+    }
+
+    /// This bundle's header
+    pub fn header(&self) -> &BundleV1Header {
+        &self.reader.header
+    }
+
+    /// This bundle's index
+    pub fn index(&self) -> &BundleV1Index {
+        &self.reader.index
+    }
+
+    /// This bundle's hash, as a hex string
+    pub fn hash(&self) -> String {
+        self.reader.header.hash_string()
+    }
+
+    /// Find the path of the file Tectonic would load for `name`,
+    /// using the search profile `profile`.
+    pub fn resolve_with(&mut self, profile: &str, name: &str) -> Result<Option<&str>> {
+        if !self.search.contains_key(profile) {
+            let rules = match self.reader.index.search.get(profile) {
+                Some(x) => x,
+                None => bail!("this bundle has no search profile `{profile}`"),
+            };
+            let search = SearchIndex::new(self.entries.keys().map(|x| x.as_str()), rules);
+            self.search.insert(profile.to_owned(), search);
+        }
+
+        Ok(self.search[profile].resolve(name))
+    }
+
+    /// Find the path of the file Tectonic would load for `name`,
+    /// using this bundle's default search profile.
+    pub fn resolve(&mut self, name: &str) -> Result<Option<&str>> {
+        let profile = self.reader.index.default_search.clone();
+        self.resolve_with(&profile, name)
+    }
+
+    /// Read the file at `path`, which is relative to the bundle's content dir.
+    /// Returns `None` if this bundle has no such file.
+    pub fn read_path(&mut self, path: &str) -> Result<Option<Vec<u8>>> {
+        match self.entries.get(path) {
+            Some(entry) => Ok(Some(self.reader.read_file(entry)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the file Tectonic would load for `name`,
+    /// using the search profile `profile`.
+    /// Returns `None` if no file matches.
+    pub fn open_with(&mut self, profile: &str, name: &str) -> Result<Option<Vec<u8>>> {
+        let path = match self.resolve_with(profile, name)? {
+            Some(x) => x.to_owned(),
+            None => return Ok(None),
+        };
+        self.read_path(&path)
+            .with_context(|| format!("while opening `{name}`"))
+    }
+
+    /// Read the file Tectonic would load for `name`,
+    /// using this bundle's default search profile.
+    /// Returns `None` if no file matches.
+    pub fn open(&mut self, name: &str) -> Result<Option<Vec<u8>>> {
+        let profile = self.reader.index.default_search.clone();
+        self.open_with(&profile, name)
+    }
+}
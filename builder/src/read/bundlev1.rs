@@ -0,0 +1,359 @@
+use crate::{
+    hex,
+    pack::bundlev1::{HEADER_SIZE, WIDE_HEADER_SIZE},
+};
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// A parsed ttbv1 header
+#[derive(Debug)]
+pub struct BundleV1Header {
+    pub version: u32,
+    pub index_start: u64,
+    pub index_gzip_len: u64,
+    pub index_real_len: u64,
+    pub hash: [u8; 32],
+}
+
+impl BundleV1Header {
+    /// The size of this header in bytes.
+    /// Wide bundles (version 3) have a larger header.
+    pub fn size(&self) -> u64 {
+        if self.version == 3 {
+            WIDE_HEADER_SIZE
+        } else {
+            HEADER_SIZE
+        }
+    }
+
+    /// This bundle's hash, as a hex string
+    pub fn hash_string(&self) -> String {
+        hex::encode(&self.hash)
+    }
+}
+
+/// One line of an index's `[FILELIST]`
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub start: u64,
+    pub gzip_len: u64,
+    pub real_len: u64,
+    pub hash: Option<String>,
+    pub path: String,
+}
+
+/// A `[PREFETCH:<name>]` section of an index
+#[derive(Debug, Default, Clone)]
+pub struct PrefetchGroup {
+    /// Byte range of the files stored with this group
+    pub start: u64,
+    pub len: u64,
+
+    /// Paths of all files in this group.
+    /// Files shared with other groups may be outside this group's range.
+    pub members: Vec<String>,
+}
+
+/// One line of an index's `[PARTS]`
+#[derive(Debug, Clone)]
+pub struct BundlePart {
+    /// The position of this part's first byte
+    /// in the concatenation of all parts
+    pub start: u64,
+    pub len: u64,
+
+    /// This part's file name, relative to the `.ttb` file
+    pub name: String,
+}
+
+/// A parsed ttbv1 index
+#[derive(Debug, Default)]
+pub struct BundleV1Index {
+    /// The name of the default search profile
+    pub default_search: String,
+
+    /// All search profiles, by name
+    pub search: HashMap<String, Vec<String>>,
+
+    /// All files in this bundle, in index order
+    pub filelist: Vec<IndexEntry>,
+
+    /// `key=value` pairs from `[META]`, in index order.
+    /// Keys may be repeated.
+    pub meta: Vec<(String, String)>,
+
+    /// Prefetch groups, by name
+    pub prefetch: HashMap<String, PrefetchGroup>,
+
+    /// Part files of a split bundle, in order.
+    /// This is empty if files are stored in the `.ttb` itself.
+    pub parts: Vec<BundlePart>,
+}
+
+impl BundleV1Index {
+    /// Parse the text of a ttbv1 index
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut index = BundleV1Index::default();
+        let mut section: Option<&str> = None;
+
+        for (n, line) in text.lines().enumerate() {
+            if line.starts_with('[') && line.ends_with(']') {
+                section = Some(&line[1..line.len() - 1]);
+                continue;
+            }
+
+            match section {
+                // Lines before the first section are ignored
+                None => {}
+
+                Some("DEFAULTSEARCH") => index.default_search = line.to_owned(),
+
+                Some("FILELIST") => {
+                    let mut bits = line.splitn(5, ' ');
+                    let mut next = || {
+                        bits.next()
+                            .with_context(|| format!("malformed index line {}", n + 1))
+                    };
+                    let start = next()?.parse()?;
+                    let gzip_len = next()?.parse()?;
+                    let real_len = next()?.parse()?;
+                    let hash = match next()? {
+                        "nohash" => None,
+                        x => Some(x.to_owned()),
+                    };
+                    let path = next()?.to_owned();
+
+                    index.filelist.push(IndexEntry {
+                        start,
+                        gzip_len,
+                        real_len,
+                        hash,
+                        path,
+                    });
+                }
+
+                Some("PARTS") => {
+                    let mut bits = line.splitn(3, ' ');
+                    let mut next = || {
+                        bits.next()
+                            .with_context(|| format!("malformed index line {}", n + 1))
+                    };
+                    index.parts.push(BundlePart {
+                        start: next()?.parse()?,
+                        len: next()?.parse()?,
+                        name: next()?.to_owned(),
+                    });
+                }
+
+                Some("META") => match line.split_once('=') {
+                    Some((k, v)) => index.meta.push((k.to_owned(), v.to_owned())),
+                    None => bail!("malformed index line {}", n + 1),
+                },
+
+                Some(s) => {
+                    if let Some(name) = s.strip_prefix("PREFETCH:") {
+                        // The first line of a group is its byte range
+                        match index.prefetch.get_mut(name) {
+                            Some(group) => group.members.push(line.to_owned()),
+                            None => {
+                                let (start, len) = line
+                                    .split_once(' ')
+                                    .with_context(|| format!("malformed index line {}", n + 1))?;
+                                index.prefetch.insert(
+                                    name.to_owned(),
+                                    PrefetchGroup {
+                                        start: start.parse()?,
+                                        len: len.parse()?,
+                                        members: Vec::new(),
+                                    },
+                                );
+                            }
+                        }
+                    } else if let Some(name) = s.strip_prefix("SEARCH:") {
+                        index
+                            .search
+                            .entry(name.to_owned())
+                            .or_default()
+                            .push(line.to_owned());
+                    }
+                    // Unknown sections are ignored
+                }
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Find the part that contains the byte at `start`.
+    /// Returns `None` if this bundle isn't split, or if no part has this byte.
+    pub fn part_of(&self, start: u64) -> Option<&BundlePart> {
+        let i = self.parts.partition_point(|p| p.start + p.len <= start);
+        self.parts.get(i).filter(|p| p.start <= start)
+    }
+}
+
+/// Reads files from a ttbv1 bundle
+pub struct BundleV1Reader<R: Read + Seek> {
+    reader: R,
+
+    /// The directory this bundle is in, used to find part files
+    dir: Option<PathBuf>,
+
+    pub header: BundleV1Header,
+    pub index: BundleV1Index,
+}
+
+impl BundleV1Reader<BufReader<File>> {
+    /// Open the bundle at `path`
+    pub fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("while opening `{}`", path.display()))?;
+        let mut reader = Self::new(BufReader::new(file))?;
+        reader.dir = path.parent().map(|x| x.to_path_buf());
+        Ok(reader)
+    }
+}
+
+impl<R: Read + Seek> BundleV1Reader<R> {
+    /// Read a bundle's header and index from `reader`
+    pub fn new(mut reader: R) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut magic = [0u8; 14];
+        reader
+            .read_exact(&mut magic)
+            .context("while reading bundle header")?;
+        if &magic != b"tectonicbundle" {
+            bail!("this is not a tectonic bundle");
+        }
+
+        let mut u32_buf = [0u8; 4];
+        let mut u64_buf = [0u8; 8];
+        let mut hash = [0u8; 32];
+
+        reader.read_exact(&mut u32_buf)?;
+        let version = u32::from_le_bytes(u32_buf);
+        // Version 2 bundles are split into parts,
+        // and version 3 bundles have a wide header.
+        // Otherwise, these are the same as version 1.
+        if !(1..=3).contains(&version) {
+            bail!("unsupported bundle version {version}");
+        }
+
+        reader.read_exact(&mut u64_buf)?;
+        let index_start = u64::from_le_bytes(u64_buf);
+        let (index_gzip_len, index_real_len) = if version == 3 {
+            reader.read_exact(&mut u64_buf)?;
+            let gzip_len = u64::from_le_bytes(u64_buf);
+            reader.read_exact(&mut u64_buf)?;
+            (gzip_len, u64::from_le_bytes(u64_buf))
+        } else {
+            reader.read_exact(&mut u32_buf)?;
+            let gzip_len = u32::from_le_bytes(u32_buf);
+            reader.read_exact(&mut u32_buf)?;
+            (gzip_len as u64, u32::from_le_bytes(u32_buf) as u64)
+        };
+        reader.read_exact(&mut hash)?;
+
+        let header = BundleV1Header {
+            version,
+            index_start,
+            index_gzip_len,
+            index_real_len,
+            hash,
+        };
+
+        let index = {
+            let raw = Self::read_blob(
+                &mut reader,
+                header.index_start,
+                header.index_gzip_len,
+                header.index_real_len as usize,
+            )
+            .context("while reading bundle index")?;
+            BundleV1Index::parse(&String::from_utf8(raw)?)?
+        };
+
+        if version == 2 && index.parts.is_empty() {
+            bail!("this bundle is split, but its index lists no parts");
+        }
+
+        Ok(Self {
+            reader,
+            dir: None,
+            header,
+            index,
+        })
+    }
+
+    /// Read and decompress one gzip blob
+    fn read_blob(
+        reader: &mut (impl Read + Seek),
+        start: u64,
+        gzip_len: u64,
+        real_len: usize,
+    ) -> Result<Vec<u8>> {
+        reader.seek(SeekFrom::Start(start))?;
+        let mut out = Vec::with_capacity(real_len);
+        GzDecoder::new(reader.take(gzip_len)).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    /// Read the contents of a file in this bundle
+    pub fn read_file(&mut self, entry: &IndexEntry) -> Result<Vec<u8>> {
+        if self.index.parts.is_empty() {
+            return Self::read_blob(
+                &mut self.reader,
+                entry.start,
+                entry.gzip_len,
+                entry.real_len as usize,
+            )
+            .with_context(|| format!("while reading `{}` from bundle", entry.path));
+        }
+
+        let part = self
+            .index
+            .part_of(entry.start)
+            .with_context(|| format!("no bundle part contains `{}`", entry.path))?;
+        let dir = self
+            .dir
+            .as_ref()
+            .context("can't read bundle parts without a bundle path")?;
+        let path = dir.join(&part.name);
+        let mut file =
+            File::open(&path).with_context(|| format!("while opening `{}`", path.display()))?;
+
+        Self::read_blob(
+            &mut file,
+            entry.start - part.start,
+            entry.gzip_len,
+            entry.real_len as usize,
+        )
+        .with_context(|| format!("while reading `{}` from `{}`", entry.path, part.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_size() {
+        let header = |version| BundleV1Header {
+            version,
+            index_start: 0,
+            index_gzip_len: 0,
+            index_real_len: 0,
+            hash: [0; 32],
+        };
+        assert_eq!(header(1).size(), HEADER_SIZE);
+        assert_eq!(header(2).size(), HEADER_SIZE);
+        assert_eq!(header(3).size(), WIDE_HEADER_SIZE);
+    }
+}
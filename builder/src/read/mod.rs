@@ -0,0 +1,4 @@
+pub mod bundle;
+pub mod bundlev1;
+
+pub use bundle::Bundle;
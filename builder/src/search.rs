@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+/// Finds files by name, the same way Tectonic does.
+/// See "Extra details: finding files" in `doc/bundle.md`.
+pub struct SearchIndex {
+    /// Map of file names to all paths with that name,
+    /// sorted alphabetically.
+    by_name: HashMap<String, Vec<String>>,
+
+    /// Search rules, in order
+    rules: Vec<String>,
+}
+
+impl SearchIndex {
+    /// Make a search index from the given paths (relative to the content dir)
+    /// and search rules (lines of a `SEARCH` file)
+    pub fn new<'a>(paths: impl Iterator<Item = &'a str>, rules: &[String]) -> Self {
+        let mut by_name: HashMap<String, Vec<String>> = HashMap::new();
+        for path in paths {
+            let name = path.rsplit('/').next().unwrap();
+            by_name
+                .entry(name.to_owned())
+                .or_default()
+                .push(path.to_owned());
+        }
+
+        for paths in by_name.values_mut() {
+            paths.sort();
+        }
+
+        Self {
+            by_name,
+            rules: rules.to_vec(),
+        }
+    }
+
+    /// All file names in this index, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.by_name.keys().map(|x| x.as_str())
+    }
+
+    /// Does `path` match a search rule?
+    pub fn rule_matches(rule: &str, path: &str) -> bool {
+        // Parent directory of this path, starting and ending with a slash
+        let dir = match path.rsplit_once('/') {
+            Some((parent, _)) => format!("/{parent}/"),
+            None => "/".to_owned(),
+        };
+
+        if rule.ends_with("//") {
+            // Match start of parent path
+            // (cutting off the last slash)
+            dir.starts_with(&rule[0..rule.len() - 1])
+        } else {
+            // Match full parent path
+            dir.trim_end_matches('/') == rule.trim_end_matches('/')
+        }
+    }
+
+    /// Find the path Tectonic would use for `name`.
+    /// `name` may be a plain file name (`file.tex`)
+    /// or a partial path (`path/to/file.tex`).
+    pub fn resolve(&self, name: &str) -> Option<&str> {
+        let (dir, file) = match name.rsplit_once('/') {
+            Some((d, f)) => (Some(d), f),
+            None => (None, name),
+        };
+
+        let candidates = self.by_name.get(file)?;
+
+        // Partial paths must match the end of a file's path,
+        // and must match exactly one file.
+        if let Some(dir) = dir {
+            let suffix = format!("{dir}/{file}");
+            let mut matches = candidates
+                .iter()
+                .filter(|x| *x == &suffix || x.ends_with(&format!("/{suffix}")));
+            return match (matches.next(), matches.next()) {
+                (Some(x), None) => Some(x),
+                _ => None,
+            };
+        }
+
+        if candidates.len() == 1 {
+            return Some(&candidates[0]);
+        }
+
+        // Walk search rules in order.
+        // Candidates are sorted, so the first match resolves conflicts alphabetically.
+        for rule in &self.rules {
+            if let Some(x) = candidates.iter().find(|x| Self::rule_matches(rule, x)) {
+                return Some(x);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(rules: &[&str]) -> SearchIndex {
+        let paths = [
+            "tex/latex/base/article.cls",
+            "tex/plain/base/plain.tex",
+            "tex/latex/foo/common.sty",
+            "tex/latex/bar/common.sty",
+            "tex/generic/baz/common.sty",
+        ];
+        let rules: Vec<_> = rules.iter().map(|x| x.to_string()).collect();
+        SearchIndex::new(paths.into_iter(), &rules)
+    }
+
+    #[test]
+    fn unique_name() {
+        let index = index(&[]);
+        assert_eq!(
+            index.resolve("article.cls"),
+            Some("tex/latex/base/article.cls")
+        );
+        assert_eq!(index.resolve("missing.cls"), None);
+    }
+
+    #[test]
+    fn conflict_without_rules() {
+        assert_eq!(index(&[]).resolve("common.sty"), None);
+    }
+
+    #[test]
+    fn conflict_with_rules() {
+        // Exact rules pick that directory
+        let exact = index(&["/tex/generic/baz", "/tex/latex//"]);
+        assert_eq!(
+            exact.resolve("common.sty"),
+            Some("tex/generic/baz/common.sty")
+        );
+
+        // Recursive rules pick the first match alphabetically
+        let recursive = index(&["/tex/latex//", "/tex/generic/baz"]);
+        assert_eq!(
+            recursive.resolve("common.sty"),
+            Some("tex/latex/bar/common.sty")
+        );
+    }
+
+    #[test]
+    fn partial_path() {
+        let index = index(&[]);
+        assert_eq!(
+            index.resolve("foo/common.sty"),
+            Some("tex/latex/foo/common.sty")
+        );
+        assert_eq!(
+            index.resolve("latex/bar/common.sty"),
+            Some("tex/latex/bar/common.sty")
+        );
+
+        // Suffixes must match whole directory names, and exactly one file
+        assert_eq!(index.resolve("oo/common.sty"), None);
+        assert_eq!(index.resolve("latex/common.sty"), None);
+    }
+
+    #[test]
+    fn rules() {
+        assert!(SearchIndex::rule_matches("/tex//", "tex/latex/a.sty"));
+        assert!(SearchIndex::rule_matches("/tex/latex", "tex/latex/a.sty"));
+        assert!(!SearchIndex::rule_matches("/tex", "tex/latex/a.sty"));
+        assert!(!SearchIndex::rule_matches("/te//", "tex/latex/a.sty"));
+        assert!(SearchIndex::rule_matches("/", "a.sty"));
+    }
+}
@@ -0,0 +1,457 @@
+use super::{
+    check_relative, dir::DirBundleInput, parse_config, BundleInput, InputFiles, LinkStats,
+};
+use crate::{hex, provenance::hash_file, select::spec::LinkPolicy};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, error, info, warn};
+use walkdir::WalkDir;
+
+/// Configuration for a `command` source
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommandConfig {
+    /// The command to run, as a program and its arguments
+    run: Vec<String>,
+
+    /// Files and directories this command reads, relative to the bundle dir.
+    /// These are copied into the command's working directory.
+    inputs: Option<Vec<PathBuf>>,
+
+    /// The directory the command writes files to, relative to its working directory.
+    /// This is created before the command runs. Defaults to `output`.
+    output: Option<PathBuf>,
+}
+
+/// An input made of files generated by a command.
+///
+/// The command runs in a fresh temp directory that only contains
+/// the files it declares as inputs, with a minimal environment.
+/// It is sandboxed with `bwrap` when that is available, so it can't
+/// write outside that directory or use the network.
+/// Its output is cached by a hash of the command and its inputs,
+/// so the command only runs again when one of those changes.
+pub struct CommandBundleInput {
+    bundle_dir: PathBuf,
+    run: Vec<String>,
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    links: LinkPolicy,
+
+    /// The cache key of this input, once we've computed it
+    key: Option<String>,
+    dir: Option<DirBundleInput>,
+}
+
+/// The value of `SOURCE_DATE_EPOCH`, which we pass on to commands
+fn source_date_epoch() -> Option<String> {
+    env::var("SOURCE_DATE_EPOCH").ok()
+}
+
+/// Name of the file that marks a finished cache entry.
+/// It holds that entry's key, and its modification time is when the entry was last used.
+const CACHE_MARKER: &str = "CACHE-KEY";
+
+/// Cache entries that haven't been used for this long are removed
+const CACHE_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The directory we cache command output in.
+/// This is `$XDG_CACHE_HOME/tectonic-bundle/command`, or `~/.cache/...`
+/// if `XDG_CACHE_HOME` isn't set, so that other users can't write to it.
+fn cache_dir() -> Result<PathBuf> {
+    let base = match env::var_os("XDG_CACHE_HOME").filter(|x| !x.is_empty()) {
+        Some(x) => PathBuf::from(x),
+        None => match env::var_os("HOME").filter(|x| !x.is_empty()) {
+            Some(x) => PathBuf::from(x).join(".cache"),
+            None => bail!("can't find a cache directory, set `XDG_CACHE_HOME` or `HOME`"),
+        },
+    };
+    let dir = base.join("tectonic-bundle").join("command");
+
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(&dir)
+        .with_context(|| format!("while creating `{}`", dir.display()))?;
+
+    Ok(dir)
+}
+
+/// Remove cache entries that haven't been used in a while,
+/// and partial entries left behind by runs that didn't finish.
+fn evict(cache_dir: &Path) -> Result<()> {
+    let now = SystemTime::now();
+    for entry in fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        let used = fs::metadata(path.join(CACHE_MARKER))
+            .or_else(|_| fs::metadata(&path))
+            .and_then(|x| x.modified());
+        let age = used.map(|x| now.duration_since(x).unwrap_or_default());
+
+        if age.map(|x| x > CACHE_MAX_AGE).unwrap_or(true) {
+            debug!(
+                tectonic_log_source = "select",
+                "removing old cache entry `{}`",
+                path.display()
+            );
+            if let Err(e) = fs::remove_dir_all(&path) {
+                warn!(
+                    tectonic_log_source = "select",
+                    "couldn't remove old cache entry `{}`: {e}",
+                    path.display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How we keep a command from changing anything outside its temp dir
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sandbox {
+    /// `bwrap`: the command sees a read-only filesystem, a private `/tmp`,
+    /// and no network. Only its temp dir is writable.
+    Bwrap,
+
+    /// `unshare`: the command has no network, but can reach every file we can
+    Unshare,
+
+    /// Neither of the above work here
+    None,
+}
+
+impl Sandbox {
+    /// Find the best sandbox that works on this system.
+    /// This is only checked once, since each check runs a program.
+    fn detect() -> Self {
+        static SANDBOX: OnceLock<Sandbox> = OnceLock::new();
+        *SANDBOX.get_or_init(|| {
+            let works = |program: &str, args: &[&str]| {
+                Command::new(program)
+                    .args(args)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .map(|x| x.success())
+                    .unwrap_or(false)
+            };
+
+            if works(
+                "bwrap",
+                &["--ro-bind", "/", "/", "--unshare-all", "--", "true"],
+            ) {
+                Sandbox::Bwrap
+            } else if works(
+                "unshare",
+                &["--user", "--map-root-user", "--net", "--", "true"],
+            ) {
+                Sandbox::Unshare
+            } else {
+                Sandbox::None
+            }
+        })
+    }
+
+    /// Make a command that runs `run` in this sandbox, in the directory `work`
+    fn command(self, run: &[String], work: &Path) -> Command {
+        let mut command = match self {
+            Sandbox::Bwrap => {
+                let mut command = Command::new("bwrap");
+                command
+                    .args(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"])
+                    .args(["--tmpfs", "/tmp", "--bind"])
+                    .args([work, work])
+                    .arg("--chdir")
+                    .arg(work)
+                    .args(["--unshare-all", "--die-with-parent", "--"])
+                    .args(run);
+                command
+            }
+            Sandbox::Unshare => {
+                let mut command = Command::new("unshare");
+                command
+                    .args(["--user", "--map-root-user", "--net", "--"])
+                    .args(run);
+                command
+            }
+            Sandbox::None => {
+                let mut command = Command::new(&run[0]);
+                command.args(&run[1..]);
+                command
+            }
+        };
+        command.current_dir(work);
+        command
+    }
+}
+
+/// Copy the directory `src` to `dst`, which must not exist.
+/// Symlinks are copied as links.
+fn copy_tree(src: &Path, dst: &Path) -> Result<()> {
+    for entry in WalkDir::new(src) {
+        let entry = entry?;
+        let target = dst.join(entry.path().strip_prefix(src).unwrap());
+        let kind = entry.file_type();
+
+        if kind.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if kind.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+            #[cfg(not(unix))]
+            fs::copy(entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)
+                .with_context(|| format!("while copying `{}`", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+impl CommandBundleInput {
+    pub fn from_config(bundle_dir: &Path, config: &toml::Value) -> Result<Box<dyn BundleInput>> {
+        let config: CommandConfig = parse_config(config)?;
+
+        if config.run.is_empty() {
+            bail!("`run` must not be empty");
+        }
+        for x in config.inputs.iter().flatten().chain(config.output.iter()) {
+            check_relative(x)?;
+        }
+        if let Some(output) = &config.output {
+            if output.file_name().is_none() {
+                bail!("`output` must be a directory inside the command's working directory");
+            }
+        }
+
+        Ok(Box::new(Self {
+            bundle_dir: bundle_dir.to_path_buf(),
+            run: config.run,
+            inputs: config.inputs.unwrap_or_default(),
+            output: config.output.unwrap_or(PathBuf::from("output")),
+            links: LinkPolicy::default(),
+            key: None,
+            dir: None,
+        }))
+    }
+
+    /// All files in our declared inputs, as paths relative to the bundle dir.
+    /// Directories are expanded, files are sorted by path.
+    fn input_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for input in &self.inputs {
+            let path = self.bundle_dir.join(input);
+            if !path.exists() {
+                bail!("command input `{}` doesn't exist", input.display());
+            }
+
+            for entry in WalkDir::new(&path) {
+                let entry = entry?;
+                if entry.file_type().is_file() {
+                    files.push(
+                        entry
+                            .path()
+                            .strip_prefix(&self.bundle_dir)
+                            .unwrap()
+                            .to_path_buf(),
+                    );
+                }
+            }
+        }
+
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    /// Compute this input's cache key from the command, its output
+    /// directory, `SOURCE_DATE_EPOCH`, and the hashes of its inputs.
+    fn cache_key(&mut self) -> Result<String> {
+        if let Some(key) = &self.key {
+            return Ok(key.clone());
+        }
+
+        let mut hasher = Sha256::new();
+        for arg in &self.run {
+            hasher.update(format!("run {arg}\n"));
+        }
+        hasher.update(format!("output {}\n", self.output.display()));
+        if let Some(epoch) = source_date_epoch() {
+            hasher.update(format!("epoch {epoch}\n"));
+        }
+        for file in self.input_files()? {
+            let hash = hash_file(&self.bundle_dir.join(&file))?;
+            hasher.update(format!("input {hash} {}\n", file.display()));
+        }
+
+        let key = hex::encode(&hasher.finalize());
+        self.key = Some(key.clone());
+        Ok(key)
+    }
+
+    /// Run this input's command, or reuse its cached output.
+    /// Returns the directory with the command's output.
+    fn generate(&mut self) -> Result<PathBuf> {
+        let key = self.cache_key()?;
+        let cache_dir = cache_dir()?;
+        evict(&cache_dir)?;
+        let cached = cache_dir.join(&key);
+        let marker = cached.join(CACHE_MARKER);
+
+        // Only use finished entries made for this key
+        let hit = fs::read_to_string(&marker)
+            .map(|x| x.trim() == key)
+            .unwrap_or(false);
+        if !hit && cached.exists() {
+            fs::remove_dir_all(&cached)
+                .with_context(|| format!("while removing `{}`", cached.display()))?;
+        }
+
+        if hit {
+            info!(
+                tectonic_log_source = "select",
+                "using cached output of `{}`",
+                self.run.join(" ")
+            );
+        } else {
+            // Run the command in a fresh temp dir,
+            // and only copy its output into the cache once it has succeeded.
+            let work = tempfile::Builder::new()
+                .prefix("tectonic-bundle-command-")
+                .tempdir()
+                .context("while creating a temp dir for a command")?;
+            let work = work.path();
+
+            for file in self.input_files()? {
+                let dst = work.join(&file);
+                fs::create_dir_all(dst.parent().unwrap())?;
+                fs::copy(self.bundle_dir.join(&file), &dst)
+                    .with_context(|| format!("while copying `{}`", file.display()))?;
+            }
+            fs::create_dir_all(work.join(&self.output))?;
+
+            let sandbox = Sandbox::detect();
+            match sandbox {
+                Sandbox::Bwrap => {}
+                Sandbox::Unshare => warn!(
+                    tectonic_log_source = "select",
+                    "`bwrap` isn't available, so `{}` can read and write files outside its temp dir",
+                    self.run.join(" ")
+                ),
+                Sandbox::None => warn!(
+                    tectonic_log_source = "select",
+                    "neither `bwrap` nor `unshare` work here, running `{}` without a sandbox",
+                    self.run.join(" ")
+                ),
+            }
+
+            info!(
+                tectonic_log_source = "select",
+                "running `{}`",
+                self.run.join(" ")
+            );
+
+            // Give the command a small, fixed environment
+            // so that its output doesn't depend on who runs it.
+            let mut command = sandbox.command(&self.run, work);
+            command
+                .env_clear()
+                .env("PATH", env::var_os("PATH").unwrap_or_default())
+                .env("HOME", work)
+                .env("TMPDIR", work)
+                .env("LC_ALL", "C")
+                .env("TZ", "UTC");
+            if let Some(epoch) = source_date_epoch() {
+                command.env("SOURCE_DATE_EPOCH", epoch);
+            }
+            let output = command
+                .output()
+                .with_context(|| format!("while running `{}`", self.run[0]))?;
+
+            for line in String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .chain(String::from_utf8_lossy(&output.stderr).lines())
+            {
+                if output.status.success() {
+                    debug!(tectonic_log_source = "select", "{line}");
+                } else {
+                    error!(tectonic_log_source = "select", "{line}");
+                }
+            }
+
+            if !output.status.success() {
+                bail!("`{}` failed with {}", self.run.join(" "), output.status);
+            }
+            if !work.join(&self.output).is_dir() {
+                bail!(
+                    "`{}` removed its output directory `{}`",
+                    self.run.join(" "),
+                    self.output.display()
+                );
+            }
+
+            // Build the cache entry next to the cache, then move it in place
+            let staging = cache_dir.join(format!("{key}.{}.tmp", std::process::id()));
+            if staging.exists() {
+                fs::remove_dir_all(&staging)?;
+            }
+            copy_tree(&work.join(&self.output), &staging.join(&self.output))
+                .with_context(|| format!("while caching the output of `{}`", self.run.join(" ")))?;
+            fs::write(staging.join(CACHE_MARKER), format!("{key}\n"))?;
+            fs::rename(&staging, &cached)
+                .with_context(|| format!("while caching `{}`", staging.display()))?;
+        }
+
+        // Mark this entry as used, so it isn't evicted
+        fs::write(&marker, format!("{key}\n"))?;
+
+        let output = cached.join(&self.output);
+        if !output.is_dir() {
+            bail!("cache entry `{}` is missing its output", cached.display());
+        }
+
+        Ok(output)
+    }
+}
+
+impl BundleInput for CommandBundleInput {
+    fn description(&self) -> String {
+        format!("command `{}`", self.run.join(" "))
+    }
+
+    fn hash(&mut self) -> Result<Option<String>> {
+        Ok(Some(self.cache_key()?))
+    }
+
+    fn hash_file_name(&self) -> Option<&str> {
+        Some("COMMAND-SHA256SUM")
+    }
+
+    fn set_link_policy(&mut self, policy: LinkPolicy) {
+        self.links = policy;
+    }
+
+    fn link_stats(&self) -> LinkStats {
+        self.dir
+            .as_ref()
+            .map(|x| x.link_stats())
+            .unwrap_or_default()
+    }
+
+    fn iter_files(&mut self) -> Result<InputFiles<'_>> {
+        let output = self.generate()?;
+        let dir = self.dir.insert(DirBundleInput::new(output));
+        dir.set_link_policy(self.links);
+        dir.iter_files()
+    }
+}
@@ -1,56 +1,186 @@
-use super::BundleInput;
-use anyhow::Result;
+use super::{parse_config, BadEntry, BundleInput, InputFiles, LinkStats};
+use crate::select::spec::LinkPolicy;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
 use std::{
     fs::{self},
     io::Read,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
+use tracing::warn;
 use walkdir::WalkDir;
 
+/// Configuration for a `dir` source
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DirConfig {
+    /// Path to this input's directory, relative to the bundle dir
+    path: PathBuf,
+}
+
 pub struct DirBundleInput {
+    /// Path to this input, as given in the bundle specification
+    spec_path: PathBuf,
     dir: PathBuf,
+    links: LinkPolicy,
+    link_stats: LinkStats,
 }
 
 impl DirBundleInput {
     pub fn new(dir: PathBuf) -> Self {
         Self {
-            dir: dir.canonicalize().unwrap(),
+            spec_path: dir.clone(),
+            dir,
+            links: LinkPolicy::default(),
+            link_stats: LinkStats::default(),
         }
     }
-}
 
-impl BundleInput for DirBundleInput {
-    fn iter_files(&mut self) -> impl Iterator<Item = Result<(String, Box<dyn Read + '_>)>> {
-        WalkDir::new(&self.dir)
-            .into_iter()
-            .filter_map(|x| match x {
-                Err(_) => Some(x),
-                Ok(x) => {
-                    if !x.file_type().is_file() {
-                        None
-                    } else {
-                        Some(Ok(x))
-                    }
+    pub fn from_config(bundle_dir: &Path, config: &toml::Value) -> Result<Box<dyn BundleInput>> {
+        let config: DirConfig = parse_config(config)?;
+        Ok(Box::new(Self {
+            dir: bundle_dir.join(&config.path),
+            spec_path: config.path,
+            links: LinkPolicy::default(),
+            link_stats: LinkStats::default(),
+        }))
+    }
+
+    /// List all files in `disk_dir`, which is at `prefix` in this input.
+    /// Returns `(path in input, path on disk)` pairs,
+    /// or a [`BadEntry`] error for each entry we can't read.
+    ///
+    /// `root` is the canonical path of this input's root,
+    /// `walking` holds the canonical paths of all directories we're in,
+    /// so that links to them don't make us loop forever.
+    fn list(
+        &mut self,
+        root: &Path,
+        disk_dir: &Path,
+        prefix: &Path,
+        walking: &mut Vec<PathBuf>,
+        out: &mut Vec<Result<(String, PathBuf)>>,
+    ) -> Result<()> {
+        walking.push(disk_dir.to_path_buf());
+
+        for entry in WalkDir::new(disk_dir).min_depth(1) {
+            let entry = match entry {
+                Ok(x) => x,
+                Err(e) => {
+                    let location = match e.path() {
+                        Some(x) => format!("`{}`", x.display()),
+                        None => format!("in `{}`", disk_dir.display()),
+                    };
+                    out.push(Err(BadEntry::error(location, e)));
+                    continue;
                 }
-            })
-            .map(|x| match x {
-                Ok(x) => {
-                    let path = x
-                        .into_path()
-                        .canonicalize()
-                        .unwrap()
-                        .strip_prefix(&self.dir)
-                        .unwrap()
-                        .to_str()
-                        .unwrap()
-                        .to_string();
-
-                    Ok((
-                        path.clone(),
-                        Box::new(fs::File::open(self.dir.join(path))?) as Box<dyn Read>,
-                    ))
+            };
+            let path = prefix.join(entry.path().strip_prefix(disk_dir)?);
+            let path_str = match path.to_str() {
+                Some(x) => x.to_owned(),
+                None => {
+                    out.push(Err(BadEntry::non_utf8(format!("`{}`", path.display()))));
+                    continue;
                 }
-                Err(e) => Err(anyhow::Error::from(e)),
-            })
+            };
+
+            if entry.file_type().is_file() {
+                out.push(Ok((path_str, entry.into_path())));
+                continue;
+            }
+
+            if !entry.path_is_symlink() {
+                continue;
+            }
+
+            match self.links {
+                LinkPolicy::Error => bail!("`{path_str}` is a symlink"),
+                LinkPolicy::Skip => {
+                    warn!(
+                        tectonic_log_source = "select",
+                        "skipping symlink `{path_str}`"
+                    );
+                    self.link_stats.skipped += 1;
+                    continue;
+                }
+                LinkPolicy::Follow | LinkPolicy::Copy => {}
+            }
+
+            let target = match fs::canonicalize(entry.path()) {
+                Ok(x) => x,
+                Err(_) => {
+                    warn!(
+                        tectonic_log_source = "select",
+                        "symlink `{path_str}` is broken, skipping it"
+                    );
+                    self.link_stats.skipped += 1;
+                    continue;
+                }
+            };
+
+            if self.links == LinkPolicy::Follow && !target.starts_with(root) {
+                warn!(
+                    tectonic_log_source = "select",
+                    "symlink `{path_str}` points outside this input, skipping it"
+                );
+                self.link_stats.skipped += 1;
+                continue;
+            }
+
+            if target.is_file() {
+                out.push(Ok((path_str, target)));
+            } else if walking.iter().any(|x| x.starts_with(&target))
+                || entry
+                    .path()
+                    .parent()
+                    .and_then(|x| fs::canonicalize(x).ok())
+                    .map(|x| x.starts_with(&target))
+                    .unwrap_or(false)
+            {
+                warn!(
+                    tectonic_log_source = "select",
+                    "symlink `{path_str}` points to a directory that contains it, skipping it"
+                );
+                self.link_stats.skipped += 1;
+                continue;
+            } else {
+                self.list(root, &target, &path, walking, out)?;
+            }
+            self.link_stats.followed += 1;
+        }
+
+        walking.pop();
+        Ok(())
+    }
+}
+
+impl BundleInput for DirBundleInput {
+    fn description(&self) -> String {
+        format!("dir `{}`", self.spec_path.display())
+    }
+
+    fn set_link_policy(&mut self, policy: LinkPolicy) {
+        self.links = policy;
+    }
+
+    fn link_stats(&self) -> LinkStats {
+        self.link_stats
+    }
+
+    fn iter_files(&mut self) -> Result<InputFiles<'_>> {
+        let root = self
+            .dir
+            .canonicalize()
+            .with_context(|| format!("while opening `{}`", self.dir.display()))?;
+
+        let mut files = Vec::new();
+        self.list(&root, &root, Path::new(""), &mut Vec::new(), &mut files)?;
+
+        Ok(Box::new(files.into_iter().map(|x| {
+            let (path, disk_path) = x?;
+            let file = fs::File::open(&disk_path)
+                .with_context(|| format!("while opening `{}`", disk_path.display()))?;
+            Ok((path, Box::new(file) as Box<dyn Read>))
+        })))
     }
 }
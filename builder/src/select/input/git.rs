@@ -0,0 +1,208 @@
+use super::{parse_config, BadEntry, BundleInput, InputFiles};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::{
+    io::{BufRead, BufReader, Cursor, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+use tracing::info;
+
+/// Configuration for a `git` source
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GitConfig {
+    /// Path to a local repository, relative to the bundle dir
+    path: PathBuf,
+
+    /// The commit, tag, or branch to read files from
+    rev: String,
+
+    /// The directory in this repository that is this input's root
+    subdir: Option<String>,
+}
+
+/// Reads blobs from a repository with `git cat-file --batch`
+struct CatFile {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl CatFile {
+    fn new(repo: &Path) -> Result<Self> {
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("while spawning `git cat-file`")?;
+
+        Ok(Self {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+        })
+    }
+
+    /// Read the blob with the given object id
+    fn read(&mut self, oid: &str) -> Result<Vec<u8>> {
+        writeln!(self.stdin, "{oid}")?;
+        self.stdin.flush()?;
+
+        // Each object is `<oid> <type> <size>\n<data>\n`
+        let mut header = String::new();
+        self.stdout.read_line(&mut header)?;
+        let size = match header.trim_end().split(' ').collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse::<usize>()?,
+            _ => bail!(
+                "unexpected output from `git cat-file`: `{}`",
+                header.trim_end()
+            ),
+        };
+
+        let mut data = vec![0u8; size + 1];
+        self.stdout.read_exact(&mut data)?;
+        data.pop();
+        Ok(data)
+    }
+}
+
+impl Drop for CatFile {
+    fn drop(&mut self) {
+        // Don't leave `git cat-file` running
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub struct GitBundleInput {
+    /// Path to this repository, as given in the bundle specification
+    spec_path: PathBuf,
+    path: PathBuf,
+    rev: String,
+    subdir: Option<String>,
+
+    /// The commit `rev` points to, once we've resolved it
+    commit: Option<String>,
+    cat_file: Option<CatFile>,
+}
+
+impl GitBundleInput {
+    pub fn from_config(bundle_dir: &Path, config: &toml::Value) -> Result<Box<dyn BundleInput>> {
+        let config: GitConfig = parse_config(config)?;
+        Ok(Box::new(Self {
+            path: bundle_dir.join(&config.path),
+            spec_path: config.path,
+            rev: config.rev,
+            subdir: config
+                .subdir
+                .map(|x| x.trim_matches('/').to_owned())
+                .filter(|x| !x.is_empty()),
+            commit: None,
+            cat_file: None,
+        }))
+    }
+
+    /// Run git in this repository and return its output
+    fn git(&self, args: &[&str]) -> Result<Vec<u8>> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.path)
+            .args(args)
+            .output()
+            .with_context(|| format!("while running `git {}`", args[0]))?;
+
+        if !output.status.success() {
+            bail!(
+                "`git {}` failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(output.stdout)
+    }
+
+    /// Find the commit this input's revision points to
+    fn resolve(&mut self) -> Result<String> {
+        if let Some(commit) = &self.commit {
+            return Ok(commit.clone());
+        }
+
+        let out = self.git(&["rev-parse", "--verify", &format!("{}^{{commit}}", self.rev)])?;
+        let commit = String::from_utf8(out)?.trim().to_owned();
+        self.commit = Some(commit.clone());
+        Ok(commit)
+    }
+}
+
+impl BundleInput for GitBundleInput {
+    fn description(&self) -> String {
+        match &self.subdir {
+            Some(subdir) => format!("git `{}` {} `{subdir}`", self.spec_path.display(), self.rev),
+            None => format!("git `{}` {}", self.spec_path.display(), self.rev),
+        }
+    }
+
+    fn hash(&mut self) -> Result<Option<String>> {
+        let commit = self.resolve()?;
+        info!(
+            tectonic_log_source = "select",
+            "`{}` is commit {commit}", self.rev
+        );
+        Ok(Some(commit))
+    }
+
+    fn hash_file_name(&self) -> Option<&str> {
+        Some("GIT-COMMIT")
+    }
+
+    fn iter_files(&mut self) -> Result<InputFiles<'_>> {
+        let commit = self.resolve()?;
+        let tree = match &self.subdir {
+            Some(subdir) => format!("{commit}:{subdir}"),
+            None => commit,
+        };
+
+        // Each entry is `<mode> <type> <oid>\t<path>\0`
+        let out = self.git(&["ls-tree", "-r", "-z", &tree])?;
+        let mut blobs = Vec::new();
+        let mut bad = Vec::new();
+        for entry in out.split(|x| *x == 0).filter(|x| !x.is_empty()) {
+            let entry = match std::str::from_utf8(entry) {
+                Ok(x) => x,
+                Err(_) => {
+                    let entry = String::from_utf8_lossy(entry);
+                    let path = entry.split_once('\t').map(|x| x.1).unwrap_or(&entry);
+                    bad.push(BadEntry::non_utf8(format!("`{path}` in `{tree}`")));
+                    continue;
+                }
+            };
+            let (meta, path) = match entry.split_once('\t') {
+                Some(x) => x,
+                None => bail!("unexpected output from `git ls-tree`: `{entry}`"),
+            };
+
+            // Only add regular files, like other inputs.
+            // This skips symlinks (120000) and submodules (160000).
+            match meta.split(' ').collect::<Vec<_>>()[..] {
+                ["100644" | "100755", "blob", oid] => blobs.push((path.to_owned(), oid.to_owned())),
+                [_, _, _] => continue,
+                _ => bail!("unexpected output from `git ls-tree`: `{entry}`"),
+            }
+        }
+
+        let cat_file = self.cat_file.insert(CatFile::new(&self.path)?);
+        Ok(Box::new(bad.into_iter().map(Err).chain(
+            blobs.into_iter().map(move |(path, oid)| {
+                let data = cat_file
+                    .read(&oid)
+                    .with_context(|| format!("while reading `{path}` from git"))?;
+                Ok((path, Box::new(Cursor::new(data)) as Box<dyn Read>))
+            }),
+        )))
+    }
+}
@@ -0,0 +1,74 @@
+use super::{parse_config, BundleInput, InputFiles};
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Read},
+    path::{Component, Path},
+};
+
+/// Configuration for an `inline` source
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InlineConfig {
+    /// Files in this input, by path
+    files: BTreeMap<String, InlineFile>,
+}
+
+/// A file declared in the bundle specification
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InlineFile {
+    content: String,
+}
+
+/// An input made of files declared in the bundle specification.
+pub struct InlineBundleInput {
+    files: BTreeMap<String, String>,
+}
+
+impl InlineBundleInput {
+    pub fn from_config(_bundle_dir: &Path, config: &toml::Value) -> Result<Box<dyn BundleInput>> {
+        let config: InlineConfig = parse_config(config)?;
+
+        // Paths must be plain relative paths, like `tectonic/foo.tex`
+        for path in config.files.keys() {
+            let components = Path::new(path).components();
+            if path.is_empty()
+                || path.ends_with('/')
+                || !components
+                    .into_iter()
+                    .all(|x| matches!(x, Component::Normal(_)))
+            {
+                bail!("bad inline file path `{path}`");
+            }
+        }
+
+        Ok(Box::new(Self {
+            files: config
+                .files
+                .into_iter()
+                .map(|(path, file)| (path, file.content))
+                .collect(),
+        }))
+    }
+}
+
+impl BundleInput for InlineBundleInput {
+    fn description(&self) -> String {
+        format!("inline, {} files", self.files.len())
+    }
+
+    fn size_hint(&self) -> Option<u64> {
+        Some(self.files.values().map(|x| x.len() as u64).sum())
+    }
+
+    fn iter_files(&mut self) -> Result<InputFiles<'_>> {
+        Ok(Box::new(self.files.iter().map(|(path, content)| {
+            Ok((
+                path.clone(),
+                Box::new(Cursor::new(content.as_bytes())) as Box<dyn Read>,
+            ))
+        })))
+    }
+}
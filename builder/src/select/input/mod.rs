@@ -1,42 +1,258 @@
+//! Input backends.
+//!
+//! Every input in a bundle specification has a `source` table with one key,
+//! like `source.dir` or `source.tarball`. That key picks the backend that
+//! reads the input's files: an [`InputRegistry`] maps each key to a
+//! constructor that turns the rest of the table into a [`BundleInput`].
+//!
+//! New sources are added by implementing [`BundleInput`] and registering
+//! a constructor, without changing the file picker.
+
+mod command;
 mod dir;
+mod git;
+mod inline;
 mod tar;
 
-use anyhow::Result;
-use std::{io::Read, path::PathBuf};
+pub use command::CommandBundleInput;
+pub use dir::DirBundleInput;
+pub use git::GitBundleInput;
+pub use inline::InlineBundleInput;
+pub use tar::TarBundleInput;
+
+use super::spec::{BundleInputSource, LinkPolicy};
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+/// A file in an input: a path relative to the input's root and a reader for that file.
+/// Entries we can't read are [`BadEntry`] errors.
+pub type InputFile<'a> = Result<(String, Box<dyn Read + 'a>)>;
+
+/// An iterator over the files in an input.
+pub type InputFiles<'a> = Box<dyn Iterator<Item = InputFile<'a>> + 'a>;
+
+/// A source of bundle files.
+pub trait BundleInput {
+    /// A short description of this input, used in logs, bundle metadata,
+    /// and provenance records. e.g. "tarball `texlive.tar` <hash>"
+    fn description(&self) -> String;
+
+    /// The hash this input is expected to have, as given in the bundle specification.
+    /// Inputs without a hash return `None`.
+    fn expected_hash(&self) -> Option<&str> {
+        None
+    }
+
+    /// Compute this input's hash.
+    /// This is called once, before [`BundleInput::iter_files`].
+    /// Inputs without a hash return `None`.
+    fn hash(&mut self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// If this returns a name, this input's hash is saved in the bundle
+    /// as a file with that name, in this input's directory.
+    fn hash_file_name(&self) -> Option<&str> {
+        None
+    }
+
+    /// About how many bytes of data we'll read from this input, if we know.
+    /// This is only used for progress messages.
+    fn size_hint(&self) -> Option<u64> {
+        None
+    }
+
+    /// Use `policy` for symlinks and hardlinks in this input.
+    /// This is called before [`BundleInput::iter_files`].
+    /// Inputs that can't have links ignore this.
+    fn set_link_policy(&mut self, _policy: LinkPolicy) {}
+
+    /// How many links we've followed and skipped so far
+    fn link_stats(&self) -> LinkStats {
+        LinkStats::default()
+    }
 
-trait BundleInput {
-    #[allow(clippy::type_complexity)]
-    fn iter_files(&mut self) -> impl Iterator<Item = Result<(String, Box<dyn Read + '_>)>>;
+    /// Iterate over all files in this input.
+    fn iter_files(&mut self) -> Result<InputFiles<'_>>;
 }
 
-pub enum Input {
-    Directory(dir::DirBundleInput),
-    Tarball(tar::TarBundleInput),
+/// A file we couldn't read from an input.
+///
+/// Inputs return this error (through `anyhow`) for problems with one file,
+/// like a name that isn't valid utf-8. The picker may skip these files.
+/// Any other error stops the picker.
+#[derive(Debug)]
+pub struct BadEntry {
+    /// Where this entry is, like a path or a position in an archive
+    pub location: String,
+
+    /// What is wrong with this entry
+    pub reason: String,
+
+    /// True if this entry's only problem is a name that isn't valid utf-8.
+    /// These are handled by the bundle's path policy.
+    pub non_utf8: bool,
 }
 
-impl<'a> Input {
-    pub fn new_dir(path: PathBuf) -> Self {
-        Self::Directory(dir::DirBundleInput::new(path))
+impl BadEntry {
+    /// Make a bad entry error
+    pub fn error(location: impl Into<String>, reason: impl Display) -> anyhow::Error {
+        anyhow::Error::new(Self {
+            location: location.into(),
+            reason: reason.to_string(),
+            non_utf8: false,
+        })
     }
 
-    pub fn new_tarball(path: PathBuf, root: Option<PathBuf>) -> Result<Self> {
-        Ok(Self::Tarball(tar::TarBundleInput::new(path, root)?))
+    /// Make a bad entry error for a name that isn't valid utf-8
+    pub fn non_utf8(location: impl Into<String>) -> anyhow::Error {
+        anyhow::Error::new(Self {
+            location: location.into(),
+            reason: "this path is not valid utf-8".to_owned(),
+            non_utf8: true,
+        })
     }
+}
+
+impl Display for BadEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bad entry {}: {}", self.location, self.reason)
+    }
+}
+
+impl std::error::Error for BadEntry {}
+
+/// Counts of links an input has handled
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LinkStats {
+    /// Links we've added with their target's contents.
+    /// A link to a directory is counted once.
+    pub followed: usize,
 
-    #[allow(clippy::type_complexity)]
-    pub fn iter_files(
-        &'a mut self,
-    ) -> Box<dyn Iterator<Item = Result<(String, Box<dyn Read + 'a>)>> + 'a> {
-        match self {
-            Self::Directory(x) => Box::new(x.iter_files()),
-            Self::Tarball(x) => Box::new(x.iter_files()),
+    /// Links we've skipped because of our link policy,
+    /// or because their target doesn't exist
+    pub skipped: usize,
+}
+
+/// A function that makes an input from its source table.
+/// `bundle_dir` is the directory that contains the bundle specification,
+/// relative paths in `config` should be resolved against it.
+///
+/// Constructors shouldn't touch the filesystem, since they are also used
+/// to describe inputs after they've been selected.
+pub type InputConstructor =
+    fn(bundle_dir: &Path, config: &toml::Value) -> Result<Box<dyn BundleInput>>;
+
+/// Input backends, keyed by the tag used in `source` tables.
+pub struct InputRegistry {
+    constructors: HashMap<String, InputConstructor>,
+}
+
+impl Default for InputRegistry {
+    /// Make a registry with all inputs this crate provides
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register("command", command::CommandBundleInput::from_config);
+        registry.register("dir", dir::DirBundleInput::from_config);
+        registry.register("git", git::GitBundleInput::from_config);
+        registry.register("inline", inline::InlineBundleInput::from_config);
+        registry.register("tarball", tar::TarBundleInput::from_config);
+        registry
+    }
+}
+
+impl InputRegistry {
+    /// Make a registry with no inputs
+    pub fn empty() -> Self {
+        Self {
+            constructors: HashMap::new(),
         }
     }
 
-    pub fn hash(&self) -> Option<&str> {
-        match self {
-            Self::Directory(_) => None,
-            Self::Tarball(x) => Some(x.hash()),
+    /// Use `constructor` for sources tagged `tag`.
+    /// This replaces any constructor already registered for `tag`.
+    pub fn register(&mut self, tag: &str, constructor: InputConstructor) {
+        self.constructors.insert(tag.to_owned(), constructor);
+    }
+
+    /// Iterate over all registered tags
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.constructors.keys().map(|x| x.as_str())
+    }
+
+    /// Make an input from a bundle specification's source table
+    pub fn open(
+        &self,
+        source: &BundleInputSource,
+        bundle_dir: &Path,
+    ) -> Result<Box<dyn BundleInput>> {
+        let constructor = match self.constructors.get(&source.kind) {
+            Some(x) => x,
+            None => {
+                let mut tags = Vec::from_iter(self.tags());
+                tags.sort();
+                bail!(
+                    "unknown input source `{}`, expected one of {}",
+                    source.kind,
+                    tags.iter()
+                        .map(|x| format!("`{x}`"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+        };
+
+        constructor(bundle_dir, &source.config)
+            .with_context(|| format!("while reading `{}` input source", source.kind))
+    }
+
+    /// Describe a source without reading it.
+    /// Sources we can't open are described by their tag.
+    pub fn describe(&self, source: &BundleInputSource, bundle_dir: &Path) -> String {
+        self.open(source, bundle_dir)
+            .map(|x| x.description())
+            .unwrap_or_else(|_| source.kind.clone())
+    }
+}
+
+/// Deserialize an input's source table.
+/// This is a helper for [`InputConstructor`]s.
+pub fn parse_config<T: DeserializeOwned>(config: &toml::Value) -> Result<T> {
+    Ok(T::deserialize(config.clone())?)
+}
+
+/// Make sure `path` is relative and stays inside the directory it's relative to
+fn check_relative(path: &Path) -> Result<()> {
+    if !path
+        .components()
+        .all(|x| matches!(x, Component::Normal(_) | Component::CurDir))
+    {
+        bail!("`{}` must be a relative path without `..`", path.display());
+    }
+    Ok(())
+}
+
+/// Lexically normalize a relative path, resolving `.` and `..`.
+/// Returns `None` if this path is absolute or leaves the directory it's relative to.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(x) => out.push(x),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
         }
     }
+    Some(out)
 }
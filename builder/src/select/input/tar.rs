@@ -1,23 +1,91 @@
-use super::BundleInput;
-use anyhow::Result;
+use super::{normalize, parse_config, BadEntry, BundleInput, InputFile, InputFiles, LinkStats};
+use crate::{hex, select::spec::LinkPolicy};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
 use sha2::{Digest, Sha256};
 use std::{
-    fs::File,
-    io::{Read, Seek},
-    path::PathBuf,
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    rc::Rc,
 };
 use tar::Archive;
-use tracing::info;
+use tracing::{info, warn};
+
+/// Configuration for a `tarball` source
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TarConfig {
+    /// The sha256 of this tarball
+    hash: String,
+
+    /// Path to this tarball, relative to the bundle dir
+    path: PathBuf,
+
+    /// The directory in this tarball that is this input's root
+    root_dir: Option<PathBuf>,
+}
 
 pub struct TarBundleInput {
-    archive: Archive<File>,
+    /// Path to this tarball, as given in the bundle specification
+    spec_path: PathBuf,
+    path: PathBuf,
     root: PathBuf,
-    hash: String,
+    expected_hash: String,
+    archive: Option<Archive<File>>,
+
+    /// The archive we read link targets from, in a second pass
+    link_archive: Option<Archive<File>>,
+    links: LinkPolicy,
+    link_stats: Cell<LinkStats>,
 }
 
 impl TarBundleInput {
-    pub fn new(path: PathBuf, root: Option<PathBuf>) -> Result<Self> {
-        let path = path.canonicalize()?;
+    pub fn new(path: PathBuf, root: Option<PathBuf>, expected_hash: String) -> Self {
+        Self {
+            spec_path: path.clone(),
+            path,
+            root: root.unwrap_or(PathBuf::from("")),
+            expected_hash,
+            archive: None,
+            link_archive: None,
+            links: LinkPolicy::default(),
+            link_stats: Cell::default(),
+        }
+    }
+
+    pub fn from_config(bundle_dir: &Path, config: &toml::Value) -> Result<Box<dyn BundleInput>> {
+        let config: TarConfig = parse_config(config)?;
+        Ok(Box::new(Self {
+            path: bundle_dir.join(&config.path),
+            spec_path: config.path,
+            root: config.root_dir.unwrap_or(PathBuf::from("")),
+            expected_hash: config.hash,
+            archive: None,
+            link_archive: None,
+            links: LinkPolicy::default(),
+            link_stats: Cell::default(),
+        }))
+    }
+}
+
+impl BundleInput for TarBundleInput {
+    fn description(&self) -> String {
+        format!(
+            "tarball `{}` {}",
+            self.spec_path.display(),
+            self.expected_hash
+        )
+    }
+
+    fn expected_hash(&self) -> Option<&str> {
+        Some(&self.expected_hash)
+    }
+
+    fn hash(&mut self) -> Result<Option<String>> {
+        let path = self.path.canonicalize()?;
         let mut file = File::open(&path)?;
 
         info!(
@@ -26,54 +94,537 @@ impl TarBundleInput {
             path.to_str().unwrap()
         );
 
-        let hash = {
-            let mut hasher = Sha256::new();
-            let _ = std::io::copy(&mut file, &mut hasher)?;
-            hasher
-                .finalize()
-                .iter()
-                .map(|b| format!("{b:02x}"))
-                .collect::<Vec<_>>()
-                .concat()
+        let mut hasher = Sha256::new();
+        let _ = std::io::copy(&mut file, &mut hasher)?;
+        Ok(Some(hex::encode(&hasher.finalize())))
+    }
+
+    fn hash_file_name(&self) -> Option<&str> {
+        Some("TAR-SHA256SUM")
+    }
+
+    fn size_hint(&self) -> Option<u64> {
+        fs::metadata(&self.path).ok().map(|x| x.len())
+    }
+
+    fn set_link_policy(&mut self, policy: LinkPolicy) {
+        self.links = policy;
+    }
+
+    fn link_stats(&self) -> LinkStats {
+        self.link_stats.get()
+    }
+
+    fn iter_files(&mut self) -> Result<InputFiles<'_>> {
+        let root = &self.root;
+        let policy = self.links;
+        let stats = &self.link_stats;
+        let tar_path = &self.path;
+        let found = Rc::new(RefCell::new(FoundLinks::default()));
+        let found_first = found.clone();
+        let archive = self.archive.insert(Archive::new(File::open(&self.path)?));
+        let link_archive = self
+            .link_archive
+            .insert(Archive::new(File::open(&self.path)?));
+
+        // Regular files are read as we go. Links are collected,
+        // and read in a second pass once we know every symlink.
+        let mut next_offset = 0u64;
+        let files = archive.entries()?.filter_map(move |x| {
+            // We can't read past a bad header, so this error is fatal
+            let entry = match x {
+                Ok(x) => x,
+                Err(e) => {
+                    return Some(Err(anyhow::Error::new(e).context(format!(
+                        "while reading the tar entry at offset {next_offset} in `{}`",
+                        tar_path.display()
+                    ))))
+                }
+            };
+
+            let offset = entry.raw_header_position();
+            next_offset = entry.raw_file_position() + entry.size().div_ceil(512) * 512;
+            let location = || format!("at offset {offset} in `{}`", tar_path.display());
+
+            let kind = entry.header().entry_type();
+            let path = match entry.path() {
+                Ok(x) => x.into_owned(),
+                Err(e) => return Some(Err(BadEntry::error(location(), e))),
+            };
+
+            if kind.is_symlink() || kind.is_hard_link() {
+                let target = match entry.link_name() {
+                    Ok(Some(x)) => x.into_owned(),
+                    Ok(None) => {
+                        return Some(Err(BadEntry::error(
+                            format!("`{}` {}", path.display(), location()),
+                            "link has no target",
+                        )))
+                    }
+                    Err(e) => {
+                        return Some(Err(BadEntry::error(
+                            format!("`{}` {}", path.display(), location()),
+                            e,
+                        )))
+                    }
+                };
+
+                let mut found = found_first.borrow_mut();
+                found
+                    .add(path, &target, kind.is_symlink(), root, policy, stats)
+                    .err()
+                    .map(Err)
+            } else if !kind.is_file() || !path.starts_with(root) {
+                None
+            } else {
+                match path.strip_prefix(root).unwrap().to_str() {
+                    Some(x) => Some(Ok((x.to_owned(), Box::new(entry) as Box<dyn Read>))),
+                    None => Some(Err(BadEntry::non_utf8(format!(
+                        "`{}` {}",
+                        path.display(),
+                        location()
+                    )))),
+                }
+            }
+        });
+
+        // Links are read from a second pass over the tarball,
+        // which we start once the first pass has found every link.
+        let mut link_archive = Some(link_archive);
+        let links = std::iter::once(()).flat_map(move |_| -> InputFiles<'_> {
+            let archive = link_archive.take().unwrap();
+            match LinkFiles::new(archive, &found.borrow(), tar_path, root, policy, stats) {
+                Ok(Some(x)) => Box::new(x),
+                Ok(None) => Box::new(std::iter::empty()),
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            }
+        });
+
+        Ok(Box::new(files.chain(links)))
+    }
+}
+
+/// Links we've found in a tarball
+#[derive(Default)]
+struct FoundLinks {
+    /// Links in this input's root we should follow,
+    /// as `(link path, target path)` pairs.
+    /// Targets are relative to the tarball's root, and may be symlinks.
+    links: Vec<(PathBuf, Option<PathBuf>)>,
+
+    /// Every symlink in the tarball, so we can resolve chains of links.
+    /// `None` means this symlink points outside the tarball.
+    symlinks: HashMap<PathBuf, Option<PathBuf>>,
+
+    /// Every hardlink in the tarball and its target.
+    /// A hardlink is another name for its target's data,
+    /// so links into directories must find these too.
+    hardlinks: HashMap<PathBuf, PathBuf>,
+}
+
+impl FoundLinks {
+    /// Record a link at `path` that points to `target`
+    fn add(
+        &mut self,
+        path: PathBuf,
+        target: &Path,
+        is_symlink: bool,
+        root: &Path,
+        policy: LinkPolicy,
+        stats: &Cell<LinkStats>,
+    ) -> Result<()> {
+        // Symlinks are relative to their directory,
+        // hardlinks are relative to the tarball's root.
+        let target = if is_symlink {
+            normalize(&path.parent().unwrap_or(Path::new("")).join(target))
+        } else {
+            normalize(target)
         };
 
-        file.seek(std::io::SeekFrom::Start(0))?;
-        Ok(Self {
-            archive: Archive::new(file),
-            root: root.unwrap_or(PathBuf::from("")),
-            hash,
-        })
+        if is_symlink {
+            self.symlinks.insert(path.clone(), target.clone());
+        } else if let Some(target) = &target {
+            self.hardlinks.insert(path.clone(), target.clone());
+        }
+
+        if !path.starts_with(root) {
+            return Ok(());
+        }
+
+        match policy {
+            LinkPolicy::Error => bail!("`{}` is a link", path.display()),
+            LinkPolicy::Skip => {
+                warn!(
+                    tectonic_log_source = "select",
+                    "skipping link `{}`",
+                    path.display()
+                );
+                update(stats, |x| x.skipped += 1);
+            }
+            LinkPolicy::Follow | LinkPolicy::Copy => self.links.push((path, target)),
+        }
+
+        Ok(())
+    }
+
+    /// Resolve symlinks in `path`, which is relative to the tarball's root.
+    /// Returns `None` if this path leaves the tarball or has too many links.
+    fn resolve(&self, path: &Path) -> Option<PathBuf> {
+        let mut path = path.to_path_buf();
+
+        'hop: for _ in 0..40 {
+            let mut prefix = PathBuf::new();
+            for c in path.components() {
+                prefix.push(c);
+                if let Some(target) = self.symlinks.get(&prefix) {
+                    let rest = path.strip_prefix(&prefix).unwrap();
+                    path = normalize(&target.as_ref()?.join(rest))?;
+                    continue 'hop;
+                }
+            }
+            return Some(path);
+        }
+
+        None
+    }
+
+    /// All other names of each regular file, through hardlinks.
+    /// Keys are the paths of regular files in the tarball.
+    fn aliases(&self) -> HashMap<PathBuf, Vec<PathBuf>> {
+        let mut aliases: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+        for (path, target) in &self.hardlinks {
+            // Hardlinks may point to other hardlinks
+            let mut target = target;
+            for _ in 0..40 {
+                match self.hardlinks.get(target) {
+                    Some(x) => target = x,
+                    None => break,
+                }
+            }
+            aliases
+                .entry(target.clone())
+                .or_default()
+                .push(path.clone());
+        }
+
+        for x in aliases.values_mut() {
+            x.sort();
+        }
+        aliases
     }
 
-    pub fn hash(&self) -> &str {
-        &self.hash
+    /// Decide which links we'll follow, as `(link path, resolved target)` pairs.
+    fn targets(
+        &self,
+        root: &Path,
+        policy: LinkPolicy,
+        stats: &Cell<LinkStats>,
+    ) -> Vec<(PathBuf, PathBuf)> {
+        let mut links = Vec::new();
+        for (path, target) in &self.links {
+            let target = target.as_deref().and_then(|x| self.resolve(x));
+            match target {
+                None => {
+                    warn!(
+                        tectonic_log_source = "select",
+                        "link `{}` points outside this tarball, skipping it",
+                        path.display()
+                    );
+                    update(stats, |x| x.skipped += 1);
+                }
+                Some(t) if policy == LinkPolicy::Follow && !t.starts_with(root) => {
+                    warn!(
+                        tectonic_log_source = "select",
+                        "link `{}` points outside this input, skipping it",
+                        path.display()
+                    );
+                    update(stats, |x| x.skipped += 1);
+                }
+                Some(t) if path.starts_with(&t) => {
+                    warn!(
+                        tectonic_log_source = "select",
+                        "link `{}` points to a directory that contains it, skipping it",
+                        path.display()
+                    );
+                    update(stats, |x| x.skipped += 1);
+                }
+                Some(t) => links.push((path.clone(), t)),
+            }
+        }
+        links
     }
 }
 
-impl BundleInput for TarBundleInput {
-    fn iter_files(&mut self) -> impl Iterator<Item = Result<(String, Box<dyn Read + '_>)>> + '_ {
-        self.archive.entries().unwrap().filter_map(|x| {
-            // TODO: error handling
-            let xr = x.as_ref().unwrap();
+/// Reads the targets of links from a second pass over a tarball.
+/// Links to directories add every file in that directory.
+///
+/// Files are returned as we find them. A file that only one link
+/// points to is read straight from the tarball; a file that many
+/// links point to is held in memory until we move to the next entry.
+struct LinkFiles<'a> {
+    entries: tar::Entries<'a, File>,
+    tar_path: &'a Path,
+    root: &'a Path,
+    stats: &'a Cell<LinkStats>,
 
-            if !xr.header().entry_type().is_file() {
-                None
+    /// Links to follow, as `(link path, target, found)`
+    links: Vec<(PathBuf, PathBuf, bool)>,
+
+    /// See [`FoundLinks::aliases`]
+    aliases: HashMap<PathBuf, Vec<PathBuf>>,
+
+    /// Files from the current entry we haven't returned yet
+    pending: VecDeque<InputFile<'a>>,
+
+    done: bool,
+}
+
+impl<'a> LinkFiles<'a> {
+    /// Start reading link targets from `archive`.
+    /// Returns `None` if there are no links to follow.
+    fn new(
+        archive: &'a mut Archive<File>,
+        found: &FoundLinks,
+        tar_path: &'a Path,
+        root: &'a Path,
+        policy: LinkPolicy,
+        stats: &'a Cell<LinkStats>,
+    ) -> Result<Option<Self>> {
+        let links = found.targets(root, policy, stats);
+        if links.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            entries: archive.entries()?,
+            tar_path,
+            root,
+            stats,
+            links: links.into_iter().map(|(l, t)| (l, t, false)).collect(),
+            aliases: found.aliases(),
+            pending: VecDeque::new(),
+            done: false,
+        }))
+    }
+
+    /// Find every file the links we follow make from `entry`
+    fn read_entry(&mut self, mut entry: tar::Entry<'a, File>) -> Result<()> {
+        if !entry.header().entry_type().is_file() {
+            return Ok(());
+        }
+
+        // We've already reported bad paths in the first pass
+        let path = match entry.path() {
+            Ok(x) => x.into_owned(),
+            Err(_) => return Ok(()),
+        };
+
+        let mut files = Vec::new();
+        let names = std::iter::once(&path).chain(self.aliases.get(&path).into_iter().flatten());
+        for name in names {
+            for (link, target, found) in &mut self.links {
+                let rest = match name.strip_prefix(&*target) {
+                    Ok(x) => x,
+                    Err(_) => continue,
+                };
+
+                *found = true;
+                let file = link.join(rest);
+                match file.strip_prefix(self.root).unwrap().to_str() {
+                    Some(x) => files.push(x.to_owned()),
+                    None => self.pending.push_back(Err(BadEntry::non_utf8(format!(
+                        "`{}` in `{}`",
+                        file.display(),
+                        self.tar_path.display()
+                    )))),
+                }
+            }
+        }
+
+        if files.len() == 1 {
+            let file = files.pop().unwrap();
+            self.pending.push_back(Ok((file, Box::new(entry))));
+        } else if !files.is_empty() {
+            let mut buf = Vec::new();
+            entry
+                .read_to_end(&mut buf)
+                .with_context(|| format!("while reading `{}`", path.display()))?;
+            let data: Rc<[u8]> = buf.into();
+            for file in files {
+                self.pending
+                    .push_back(Ok((file, Box::new(Cursor::new(data.clone())))));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count the links we followed, and warn about broken ones
+    fn finish(&self) {
+        for (link, _, found) in &self.links {
+            if *found {
+                update(self.stats, |x| x.followed += 1);
             } else {
-                let path = xr.path().unwrap();
-
-                if !path.starts_with(&self.root) {
-                    None
-                } else {
-                    Some(Ok((
-                        path.strip_prefix(&self.root)
-                            .unwrap()
-                            .to_str()
-                            .unwrap()
-                            .to_string(),
-                        Box::new(x.unwrap()) as Box<dyn Read>,
-                    )))
+                warn!(
+                    tectonic_log_source = "select",
+                    "link `{}` is broken, skipping it",
+                    link.display()
+                );
+                update(self.stats, |x| x.skipped += 1);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for LinkFiles<'a> {
+    type Item = InputFile<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(x) = self.pending.pop_front() {
+                return Some(x);
+            }
+            if self.done {
+                return None;
+            }
+
+            match self.entries.next() {
+                None => {
+                    self.done = true;
+                    self.finish();
+                }
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(anyhow::Error::new(e).context(format!(
+                        "while reading link targets in `{}`",
+                        self.tar_path.display()
+                    ))));
                 }
+                Some(Ok(entry)) => {
+                    if let Err(e) = self.read_entry(entry) {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Update the link counts in `stats`
+fn update(stats: &Cell<LinkStats>, f: impl FnOnce(&mut LinkStats)) {
+    let mut x = stats.get();
+    f(&mut x);
+    stats.set(x);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tar::{Builder, EntryType, Header};
+    use tempfile::NamedTempFile;
+
+    /// Make a tarball with a file in `tex/`, a file outside it,
+    /// and links of every kind we handle.
+    fn tarball() -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        let mut builder = Builder::new(file.reopen().unwrap());
+
+        let mut add = |path: &str, kind: EntryType, data: &[u8], link: Option<&str>| {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(kind);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            if let Some(link) = link {
+                header.set_link_name(link).unwrap();
             }
-        })
+            builder.append_data(&mut header, path, data).unwrap();
+        };
+
+        add("tex/a.sty", EntryType::Regular, b"a", None);
+        add("other/b.sty", EntryType::Regular, b"b", None);
+        add("tex/link.sty", EntryType::Symlink, b"", Some("a.sty"));
+        add("tex/hard.sty", EntryType::Link, b"", Some("tex/a.sty"));
+        add(
+            "tex/out.sty",
+            EntryType::Symlink,
+            b"",
+            Some("../other/b.sty"),
+        );
+        add("tex/dir", EntryType::Symlink, b"", Some("../other"));
+        add("tex/abs.sty", EntryType::Symlink, b"", Some("/etc/passwd"));
+        add("tex/loop.sty", EntryType::Symlink, b"", Some("loop.sty"));
+
+        builder.into_inner().unwrap();
+        file
+    }
+
+    /// Read every file in `tex/` under `policy`.
+    /// Returns `(path, contents)` pairs and the link counts.
+    fn read(policy: LinkPolicy) -> Result<(Vec<(String, String)>, LinkStats)> {
+        let file = tarball();
+        let mut input = TarBundleInput::new(
+            file.path().to_owned(),
+            Some(PathBuf::from("tex")),
+            String::new(),
+        );
+        input.set_link_policy(policy);
+
+        let mut files = Vec::new();
+        for x in input.iter_files()? {
+            let (path, mut reader) = x?;
+            let mut data = String::new();
+            reader.read_to_string(&mut data)?;
+            files.push((path, data));
+        }
+        files.sort();
+
+        Ok((files, input.link_stats()))
+    }
+
+    fn files(x: &[(&str, &str)]) -> Vec<(String, String)> {
+        x.iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn skip_links() {
+        let (found, stats) = read(LinkPolicy::Skip).unwrap();
+        assert_eq!(found, files(&[("a.sty", "a")]));
+        assert_eq!((stats.followed, stats.skipped), (0, 6));
+    }
+
+    #[test]
+    fn follow_links() {
+        let (found, stats) = read(LinkPolicy::Follow).unwrap();
+        assert_eq!(
+            found,
+            files(&[("a.sty", "a"), ("hard.sty", "a"), ("link.sty", "a")])
+        );
+        // `out.sty` and `dir` leave the input, `abs.sty` leaves the tarball,
+        // and `loop.sty` never resolves.
+        assert_eq!((stats.followed, stats.skipped), (2, 4));
+    }
+
+    #[test]
+    fn copy_links() {
+        let (found, stats) = read(LinkPolicy::Copy).unwrap();
+        assert_eq!(
+            found,
+            files(&[
+                ("a.sty", "a"),
+                ("dir/b.sty", "b"),
+                ("hard.sty", "a"),
+                ("link.sty", "a"),
+                ("out.sty", "b"),
+            ])
+        );
+        assert_eq!((stats.followed, stats.skipped), (4, 2));
+    }
+
+    #[test]
+    fn error_on_links() {
+        assert!(read(LinkPolicy::Error).is_err());
     }
 }
@@ -0,0 +1,61 @@
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::{fs, path::Path};
+
+/// The license we assign to files we know nothing about
+pub const UNKNOWN_LICENSE: &str = "unknown";
+
+/// A list of license rules, read from a license map file.
+///
+/// Each non-empty line of this file is `<license> <regex>`.
+/// Lines starting with `#` are comments. The first rule
+/// whose regex matches a file's path decides its license.
+pub struct LicenseMap {
+    rules: Vec<(Regex, String)>,
+}
+
+impl LicenseMap {
+    /// Load a license map file
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("while reading license map `{}`", path.display()))?;
+
+        let mut rules = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (license, pattern) = match line.split_once(' ') {
+                Some(x) => x,
+                None => bail!("license map line {} has no pattern", n + 1),
+            };
+
+            rules.push((
+                Regex::new(&format!("^{}$", pattern.trim()))?,
+                license.to_owned(),
+            ));
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// Get the license of the file at `path`,
+    /// relative to its input's root.
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|(r, _)| r.is_match(path))
+            .map(|(_, l)| l.as_str())
+    }
+}
+
+/// Is `license` allowed?
+/// `license` may be a space-separated list,
+/// in which case all licenses in it must be allowed.
+pub fn is_allowed(license: &str, allowed: &[String]) -> bool {
+    license
+        .split_whitespace()
+        .all(|l| allowed.iter().any(|a| a == l))
+}
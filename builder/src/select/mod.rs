@@ -1,3 +1,6 @@
 pub mod input;
+pub mod license;
+pub mod pathpolicy;
 pub mod picker;
 pub mod spec;
+pub mod tlpdb;
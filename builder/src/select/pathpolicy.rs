@@ -0,0 +1,232 @@
+//! Checks that file names are safe to put in a bundle.
+//!
+//! Bundle paths may contain spaces but not newlines, and clients may
+//! unpack bundles on case-insensitive or Windows filesystems.
+//! Each rule has a [`PathSeverity`], set in `[bundle.path_policy]`.
+
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use tracing::warn;
+use unicode_normalization::UnicodeNormalization;
+
+use super::spec::{PathPolicy, PathSeverity};
+
+/// Characters that aren't allowed in Windows file names
+const WINDOWS_CHARS: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+
+/// Names that are reserved on Windows, with or without an extension
+const WINDOWS_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Why `path` isn't a valid Windows path, if it isn't
+fn windows_problem(path: &str) -> Option<String> {
+    for name in path.split('/') {
+        if let Some(c) = name.chars().find(|c| WINDOWS_CHARS.contains(c)) {
+            return Some(format!("has `{c}`, which isn't allowed on Windows"));
+        }
+
+        if name.ends_with(['.', ' ']) {
+            return Some(format!(
+                "has a name ending with a dot or space (`{name}`), which isn't allowed on Windows"
+            ));
+        }
+
+        let stem = name.split('.').next().unwrap().trim_end();
+        if WINDOWS_NAMES.iter().any(|x| x.eq_ignore_ascii_case(stem)) {
+            return Some(format!("has a name reserved on Windows (`{name}`)"));
+        }
+    }
+    None
+}
+
+/// Checks every path we add against a [`PathPolicy`]
+pub struct PathChecker {
+    policy: PathPolicy,
+
+    /// Paths we've added, by their lowercase NFC form
+    lowercase: HashMap<String, String>,
+
+    /// Paths we've added, by their NFC form
+    normalized: HashMap<String, String>,
+}
+
+impl PathChecker {
+    pub fn new(policy: PathPolicy) -> Self {
+        Self {
+            policy,
+            lowercase: HashMap::new(),
+            normalized: HashMap::new(),
+        }
+    }
+
+    /// What to do with names that aren't valid utf-8.
+    /// Defaults to `error`.
+    pub fn non_utf8(&self) -> PathSeverity {
+        self.policy.non_utf8.unwrap_or(PathSeverity::Error)
+    }
+
+    /// Check `path`, a path relative to the content dir.
+    /// Warns about every rule it breaks, and returns the most severe of their severities.
+    /// Paths that aren't skipped are remembered, so that we can find collisions.
+    ///
+    /// Returns an error if `path` breaks a rule with severity `error`.
+    pub fn check(&mut self, path: &str) -> Result<PathSeverity> {
+        let policy = &self.policy;
+        let mut problems = Vec::new();
+
+        if path.contains('\n') {
+            problems.push((
+                policy.newline.unwrap_or(PathSeverity::Error),
+                "has a newline".to_owned(),
+            ));
+        }
+
+        if path.chars().any(|c| c.is_control() && c != '\n') {
+            problems.push((
+                policy.control.unwrap_or(PathSeverity::Error),
+                "has a control character".to_owned(),
+            ));
+        }
+
+        if let Some(problem) = windows_problem(path) {
+            problems.push((policy.windows.unwrap_or(PathSeverity::Warn), problem));
+        }
+
+        // Case-insensitive filesystems often normalize names too,
+        // so normalize before ignoring case.
+        let normalized: String = path.nfc().collect();
+        let lowercase = normalized.to_lowercase();
+        if let Some(other) = self.normalized.get(&normalized) {
+            problems.push((
+                policy.normalization_collision.unwrap_or(PathSeverity::Warn),
+                format!("has the same name as `{other}` after unicode normalization"),
+            ));
+        } else if let Some(other) = self.lowercase.get(&lowercase) {
+            problems.push((
+                policy.case_collision.unwrap_or(PathSeverity::Warn),
+                format!("has the same name as `{other}`, ignoring case"),
+            ));
+        }
+
+        let severity = problems
+            .iter()
+            .map(|x| x.0)
+            .max()
+            .unwrap_or(PathSeverity::Ignore);
+
+        for (s, problem) in &problems {
+            match s {
+                PathSeverity::Error => bail!("`{}` {problem}", path.escape_debug()),
+                PathSeverity::Ignore => {}
+                _ => warn!(
+                    tectonic_log_source = "select",
+                    "`{}` {problem}",
+                    path.escape_debug()
+                ),
+            }
+        }
+
+        if severity == PathSeverity::Skip {
+            warn!(
+                tectonic_log_source = "select",
+                "skipping `{}` because of path policy",
+                path.escape_debug()
+            );
+        } else {
+            self.lowercase
+                .entry(lowercase)
+                .or_insert_with(|| path.to_owned());
+            self.normalized
+                .entry(normalized)
+                .or_insert_with(|| path.to_owned());
+        }
+
+        Ok(severity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_names() {
+        for path in [
+            "tex/a<b.sty",
+            "tex/a:b.sty",
+            "tex/a\\b.sty",
+            "tex/dir./a.sty",
+            "tex/a.sty ",
+            "tex/con.sty",
+            "tex/NUL",
+            "lpt1/a.sty",
+            "tex/aux .sty",
+        ] {
+            assert!(windows_problem(path).is_some(), "{path}");
+        }
+
+        for path in [
+            "tex/a.sty",
+            "tex/a b.sty",
+            "tex/console.sty",
+            "tex/.latexmkrc",
+        ] {
+            assert_eq!(windows_problem(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn case_collision() {
+        let mut checker = PathChecker::new(PathPolicy::default());
+        assert_eq!(checker.check("tex/a.sty").unwrap(), PathSeverity::Ignore);
+        assert_eq!(checker.check("tex/A.sty").unwrap(), PathSeverity::Warn);
+        assert_eq!(checker.check("TEX/a.STY").unwrap(), PathSeverity::Warn);
+        assert_eq!(checker.check("tex/b.sty").unwrap(), PathSeverity::Ignore);
+    }
+
+    #[test]
+    fn normalization_collision() {
+        let mut checker = PathChecker::new(PathPolicy {
+            normalization_collision: Some(PathSeverity::Error),
+            ..Default::default()
+        });
+
+        // Precomposed and decomposed "é"
+        assert!(checker.check("tex/caf\u{e9}.sty").is_ok());
+        assert!(checker.check("tex/cafe\u{301}.sty").is_err());
+
+        // A different case after normalization is a case collision
+        assert_eq!(
+            checker.check("tex/CAFE\u{301}.sty").unwrap(),
+            PathSeverity::Warn
+        );
+    }
+
+    #[test]
+    fn skipped_paths_are_forgotten() {
+        let mut checker = PathChecker::new(PathPolicy {
+            windows: Some(PathSeverity::Skip),
+            case_collision: Some(PathSeverity::Error),
+            ..Default::default()
+        });
+        assert_eq!(checker.check("tex/AUX.sty").unwrap(), PathSeverity::Skip);
+        assert_eq!(checker.check("tex/aux.sty").unwrap(), PathSeverity::Skip);
+        assert!(checker.check("tex/a.sty").is_ok());
+        assert!(checker.check("tex/A.sty").is_err());
+    }
+
+    #[test]
+    fn control_characters() {
+        let mut checker = PathChecker::new(PathPolicy::default());
+        assert!(checker.check("tex/a\nb.sty").is_err());
+        assert!(checker.check("tex/a\tb.sty").is_err());
+
+        let mut checker = PathChecker::new(PathPolicy {
+            newline: Some(PathSeverity::Skip),
+            ..Default::default()
+        });
+        assert_eq!(checker.check("tex/a\nb.sty").unwrap(), PathSeverity::Skip);
+    }
+}
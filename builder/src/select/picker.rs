@@ -9,16 +9,23 @@ use std::{
     io::{self, Cursor, Read, Write},
     path::{Path, PathBuf},
     process::{Command, Stdio},
+    time::{Duration, Instant},
 };
 use tracing::{debug, error, info, trace, warn};
 use walkdir::WalkDir;
 
-use crate::cli::Cli;
+use crate::{
+    hex,
+    provenance::{self, FileRecord, InputRecord, PatchRecord, PROVENANCE_FILE},
+};
 
 use super::{
-    input::Input,
+    input::{BadEntry, InputRegistry},
+    license::{self, LicenseMap, UNKNOWN_LICENSE},
+    pathpolicy::PathChecker,
     spec::BundleSearchOrder,
-    spec::{BundleInputSource, BundleSpec},
+    spec::{BundleSpec, BundleTlpdb, PathSeverity},
+    tlpdb::{Tlpdb, TlpdbOwner},
 };
 
 #[derive(Default)]
@@ -32,11 +39,42 @@ pub struct PickStatistics {
     /// Total number of files ignored
     ignored: usize,
 
+    /// Total number of files that matched no include pattern
+    not_included: usize,
+
+    /// Total number of files excluded because of their license
+    license_excluded: usize,
+
     /// Total number of patches applied
     patch_applied: usize,
 
     /// Total number of patches found
     patch_found: usize,
+
+    /// Total number of links we added with their target's contents
+    links_followed: usize,
+
+    /// Total number of links we skipped
+    links_skipped: usize,
+
+    /// Total number of bad entries we skipped
+    bad_entries: usize,
+
+    /// Total number of files we added with a path policy warning
+    path_warnings: usize,
+
+    /// Total number of files we skipped because of the path policy
+    path_skipped: usize,
+
+    /// Total number of bytes we copied into the content dir,
+    /// before patching
+    bytes_copied: u64,
+
+    /// Total number of patched files we hashed again
+    rehashed: usize,
+
+    /// How long it took to select files
+    elapsed: Duration,
 }
 
 impl PickStatistics {
@@ -47,10 +85,33 @@ impl PickStatistics {
                 "=============== Summary ===============\n",
                 "    file conflicts:       {}\n",
                 "    files ignored:        {}\n",
+                "    files not included:   {}\n",
+                "    license exclusions:   {}\n",
                 "    diffs applied/found:  {}/{}\n",
+                "    links followed:       {}\n",
+                "    links skipped:        {}\n",
+                "    bad entries skipped:  {}\n",
+                "    path warnings:        {}\n",
+                "    paths skipped:        {}\n",
+                "    files rehashed:       {}\n",
+                "    data copied:          {:.1} MiB\n",
+                "    select time:          {:.1}s\n",
                 "    =============================\n",
             ),
-            self.conflicts, self.ignored, self.patch_applied, self.patch_found,
+            self.conflicts,
+            self.ignored,
+            self.not_included,
+            self.license_excluded,
+            self.patch_applied,
+            self.patch_found,
+            self.links_followed,
+            self.links_skipped,
+            self.bad_entries,
+            self.path_warnings,
+            self.path_skipped,
+            self.rehashed,
+            self.bytes_copied as f64 / (1024.0 * 1024.0),
+            self.elapsed.as_secs_f64(),
         );
 
         let mut sum = 0;
@@ -71,10 +132,67 @@ impl PickStatistics {
     }
 }
 
+/// A writer that computes the sha256 of everything written through it
+struct HashWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> HashWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Returns the hex sha256 and length of everything we've written
+    fn finish(self) -> (String, u64) {
+        (hex::encode(&self.hasher.finalize()), self.bytes)
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Read a diff from the patch dir.
+/// Returns its first line, which names the files it patches, and the diff itself.
+fn read_diff(path: &Path) -> Result<(String, String)> {
+    let diff_file = fs::read_to_string(path)
+        .with_context(|| format!("while reading diff `{}`", path.display()))?;
+    match diff_file.split_once('\n') {
+        Some((target, diff)) if !target.trim().is_empty() => {
+            Ok((target.to_owned(), diff.to_owned()))
+        }
+        _ => Err(BadEntry::error(
+            format!("diff `{}`", path.display()),
+            "the first line must name the files this diff patches",
+        )),
+    }
+}
+
 struct FileListEntry {
     /// Path relative to content dir (does not start with a slash)
     path: PathBuf,
     hash: Option<String>,
+
+    /// The TeX Live package this file belongs to, if we know it
+    package: Option<String>,
+
+    /// This file's license, if we know it
+    license: Option<String>,
 }
 
 impl Display for FileListEntry {
@@ -85,7 +203,7 @@ impl Display for FileListEntry {
                 Some(s) => &s,
                 None => "nohash",
             },
-            self.path.to_str().unwrap(),
+            self.path.display(),
         )
         .fmt(f)
     }
@@ -106,7 +224,23 @@ pub struct FilePicker {
     /// This map's keys are the `path` value of `FileListEntry`.
     filelist: HashMap<PathBuf, FileListEntry>,
 
+    /// Provenance records of all inputs we've added so far
+    pub inputs: Vec<InputRecord>,
+
     bundle_spec: BundleSpec,
+
+    /// Input backends, used to read each source
+    registry: InputRegistry,
+
+    /// Checks the names of files we add
+    paths: PathChecker,
+
+    /// When we started selecting files
+    started: Instant,
+
+    /// Entries we skipped because we couldn't read them,
+    /// as `<source> <entry>` lines.
+    bad_entries: Vec<String>,
 }
 
 impl FilePicker {
@@ -156,6 +290,67 @@ impl FilePicker {
         Ok(output)
     }
 
+    /// Compile global and input regex patterns into one list.
+    /// Global patterns match `/<source>/<path>`, input patterns
+    /// match paths relative to that input's root.
+    fn compile_patterns(
+        global: Option<&Vec<String>>,
+        input: Option<&Vec<String>>,
+        source: &str,
+    ) -> Result<Vec<Regex>> {
+        let mut patterns = Vec::new();
+
+        for x in global.into_iter().flatten() {
+            patterns.push(Regex::new(&format!("^{x}$"))?);
+        }
+
+        for x in input.into_iter().flatten() {
+            patterns.push(Regex::new(&format!("^/{source}/{x}$"))?);
+        }
+
+        Ok(patterns)
+    }
+
+    /// Load a tlpdb and map every file it knows about in this input
+    /// to the package that file belongs to.
+    ///
+    /// If this tlpdb selects packages, only files from those packages
+    /// (and their dependencies) are returned.
+    fn load_tlpdb(&self, tlpdb: &BundleTlpdb) -> Result<HashMap<String, TlpdbOwner>> {
+        let db = Tlpdb::load(&self.bundle_dir.join(&tlpdb.path))?;
+        let prefix = tlpdb.prefix.as_deref().unwrap_or("texmf-dist");
+
+        if !tlpdb.selects() {
+            return Ok(db.file_owners(None, prefix));
+        }
+
+        let empty = Vec::new();
+        let requested = [
+            (&tlpdb.packages, None),
+            (&tlpdb.collections, Some("Collection")),
+            (&tlpdb.schemes, Some("Scheme")),
+        ];
+        for (names, category) in &requested {
+            for name in names.as_ref().unwrap_or(&empty) {
+                db.check(name, *category)?;
+            }
+        }
+
+        let packages = db.resolve(
+            requested
+                .iter()
+                .flat_map(|(names, _)| names.as_ref().unwrap_or(&empty).iter()),
+        );
+
+        info!(
+            tectonic_log_source = "select",
+            "tlpdb selected {} packages",
+            packages.len()
+        );
+
+        Ok(db.file_owners(Some(&packages), prefix))
+    }
+
     /// Patch a file in-place.
     /// This should be done after calling `add_file`.
     fn apply_patch(
@@ -169,16 +364,28 @@ impl FilePicker {
             return Ok(false);
         }
 
-        info!(
-            tectonic_log_source = "select",
-            "patching `{}`",
-            path_in_source.to_str().unwrap()
-        );
+        let target = path_in_source
+            .to_str()
+            .ok_or_else(|| BadEntry::non_utf8(format!("`{}`", path_in_source.display())))?;
+        info!(tectonic_log_source = "select", "patching `{target}`");
         self.stats.patch_applied += 1;
 
-        // Discard first line of diff
-        let diff_file = fs::read_to_string(&diffs[path_in_source]).unwrap();
-        let (_, diff) = diff_file.split_once('\n').unwrap();
+        let diff_path = &diffs[path_in_source];
+        let (_, diff) = read_diff(diff_path)?;
+
+        if let Some(input) = self.inputs.last_mut() {
+            let diff = diff_path
+                .strip_prefix(&self.bundle_dir)
+                .unwrap_or(diff_path);
+            input.patches.push(PatchRecord {
+                diff: diff
+                    .to_str()
+                    .ok_or_else(|| BadEntry::non_utf8(format!("diff `{}`", diff.display())))?
+                    .to_owned(),
+                target: target.to_owned(),
+                hash: provenance::hash_file(diff_path)?,
+            });
+        }
 
         let mut child = Command::new("patch")
             .arg("--quiet")
@@ -188,18 +395,25 @@ impl FilePicker {
             .spawn()
             .context("while spawning `patch`")?;
 
-        let mut stdin = child.stdin.take().unwrap();
+        let mut stdin = child
+            .stdin
+            .take()
+            .context("couldn't open `patch`'s stdin")?;
         stdin
             .write_all(diff.as_bytes())
             .context("while passing diff to `patch`")?;
         drop(stdin);
-        child.wait().context("while waiting for `patch`")?;
+        let status = child.wait().context("while waiting for `patch`")?;
+        if !status.success() {
+            bail!("`patch` failed with {status}");
+        }
 
         Ok(true)
     }
 
-    /// Add a file into the file list.
-    fn add_to_filelist(&mut self, path: PathBuf, file: Option<&Path>) -> Result<()> {
+    /// Add a file into the file list, with its hex sha256.
+    /// Special files have no hash.
+    fn add_to_filelist(&mut self, path: PathBuf, hash: Option<String>) -> Result<()> {
         trace!(
             tectonic_log_source = "select",
             "adding `{path:?}` to file list"
@@ -209,39 +423,24 @@ impl FilePicker {
             path.clone(),
             FileListEntry {
                 path: path.clone(),
-                hash: match file {
-                    None => None,
-                    Some(f) => {
-                        let mut hasher = Sha256::new();
-                        let _ = std::io::copy(
-                            &mut fs::File::open(f)
-                                .with_context(|| format!("while computing hash of {path:?}"))?,
-                            &mut hasher,
-                        )?;
-                        Some(
-                            hasher
-                                .finalize()
-                                .iter()
-                                .map(|b| format!("{b:02x}"))
-                                .collect::<Vec<_>>()
-                                .concat(),
-                        )
-                    }
-                },
+                package: None,
+                license: None,
+                hash,
             },
         );
 
         Ok(())
     }
 
-    /// Add a file to this picker's content directory
+    /// Add a file to this picker's content directory.
+    /// Returns `false` if we skipped this file.
     fn add_file(
         &mut self,
         path_in_source: &Path,
         source: &str,
         file_content: &mut dyn Read,
         diffs: &HashMap<PathBuf, PathBuf>,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         let target_path = self
             .build_dir
             .join("content")
@@ -250,7 +449,7 @@ impl FilePicker {
 
         // Path to this file, relative to content dir
         let rel = target_path
-            .strip_prefix(&self.build_dir.join("content"))
+            .strip_prefix(self.build_dir.join("content"))
             .unwrap()
             .to_path_buf();
 
@@ -266,7 +465,16 @@ impl FilePicker {
                 tectonic_log_source = "select",
                 "{path_in_source:?} from source `{source}` already exists, skipping"
             );
-            return Ok(());
+            return Ok(false);
+        }
+
+        match self.paths.check(&rel.to_string_lossy())? {
+            PathSeverity::Skip => {
+                self.stats.path_skipped += 1;
+                return Ok(false);
+            }
+            PathSeverity::Warn => self.stats.path_warnings += 1,
+            _ => {}
         }
 
         fs::create_dir_all(match target_path.parent() {
@@ -275,24 +483,31 @@ impl FilePicker {
         })
         .context("failed to create content directory")?;
 
-        // Copy to content dir.
-        let mut file = fs::File::create(&target_path)?;
+        // Copy to content dir, hashing as we go
+        let mut file = HashWriter::new(fs::File::create(&target_path)?);
         io::copy(file_content, &mut file).with_context(|| {
             format!("while writing file `{path_in_source:?}` from source `{source}`")
         })?;
+        let (mut hash, bytes) = file.finish();
+        self.stats.bytes_copied += bytes;
 
-        // Apply patch if one exists
-        self.apply_patch(&target_path, path_in_source, diffs)
+        // Apply patch if one exists.
+        // Patched files must be hashed again.
+        if self
+            .apply_patch(&target_path, path_in_source, diffs)
             .with_context(|| {
                 format!("while patching `{path_in_source:?}` from source `{source}`")
-            })?;
+            })?
+        {
+            hash = provenance::hash_file(&target_path)?;
+            self.stats.rehashed += 1;
+        }
 
-        self.add_to_filelist(rel, Some(&target_path))
-            .with_context(|| {
-                format!("while adding file `{path_in_source:?}` from source `{source}`")
-            })?;
+        self.add_to_filelist(rel, Some(hash)).with_context(|| {
+            format!("while adding file `{path_in_source:?}` from source `{source}`")
+        })?;
 
-        Ok(())
+        Ok(true)
     }
 }
 
@@ -312,11 +527,22 @@ impl FilePicker {
             bundle_dir,
             build_dir,
             filelist: HashMap::new(),
+            inputs: Vec::new(),
+            paths: PathChecker::new(bundle_spec.bundle.path_policy.clone().unwrap_or_default()),
             bundle_spec,
             stats: PickStatistics::default(),
+            started: Instant::now(),
+            registry: InputRegistry::default(),
+            bad_entries: Vec::new(),
         })
     }
 
+    /// The input backends used by this picker.
+    /// Register new backends here before adding sources that use them.
+    pub fn registry_mut(&mut self) -> &mut InputRegistry {
+        &mut self.registry
+    }
+
     /// Iterate over this bundle's sources
     pub fn iter_sources(&self) -> impl Iterator<Item = &String> {
         self.bundle_spec.inputs.keys()
@@ -324,12 +550,23 @@ impl FilePicker {
 
     /// Add a directory of files to this bundle under `source_name`,
     /// applying patches and checking for replacements.
-    pub fn add_source(&mut self, cli: &Cli, source: &str) -> Result<()> {
+    ///
+    /// If `allow_hash_mismatch` is true, don't fail when an input's hash
+    /// doesn't match the hash in the bundle specification.
+    pub fn add_source(&mut self, source: &str, allow_hash_mismatch: bool) -> Result<()> {
         info!(tectonic_log_source = "select", "adding source `{source}`");
 
         let input = self.bundle_spec.inputs.get(source).unwrap();
         let mut added = 0usize;
 
+        self.inputs.push(InputRecord {
+            name: source.to_owned(),
+            source: input.source().kind,
+            hash: None,
+            patches: Vec::new(),
+            files: Vec::new(),
+        });
+
         // Load diff files
         let diffs = input
             .patch_dir
@@ -351,12 +588,11 @@ impl FilePicker {
                     }
 
                     // Read first line of diff to get target path
-                    let diff_file = fs::read_to_string(&entry).unwrap();
-                    let (target, _) = diff_file.split_once('\n').unwrap();
+                    let (target, _) = read_diff(&entry)?;
 
                     trace!(tectonic_log_source = "select", "adding diff {entry:?}");
 
-                    for t in Self::expand_search_line(target)?
+                    for t in Self::expand_search_line(&target)?
                         .into_iter()
                         .map(PathBuf::from)
                     {
@@ -377,114 +613,152 @@ impl FilePicker {
             })
             .unwrap_or(Ok(HashMap::new()))?;
 
-        // Load and compile ignore patterns
-        let ignore_patterns = {
-            // Global patterns
-            let mut ignore = self
-                .bundle_spec
-                .bundle
-                .ignore
-                .as_ref()
-                .map(|v| {
-                    v.iter()
-                        .map(|x| Regex::new(&format!("^{x}$")))
-                        .collect::<Result<Vec<Regex>, regex::Error>>()
-                })
-                .unwrap_or(Ok(Vec::new()))?;
-
-            // Input patterns
-            ignore.extend(
-                input
-                    .ignore
-                    .as_ref()
-                    .map(|v| {
-                        v.iter()
-                            .map(|x| Regex::new(&format!("^/{source}/{x}$")))
-                            .collect::<Result<Vec<Regex>, regex::Error>>()
-                    })
-                    .unwrap_or(Ok(Vec::new()))?,
+        // Load and compile include and ignore patterns
+        let include_patterns = Self::compile_patterns(
+            self.bundle_spec.bundle.include.as_ref(),
+            input.include.as_ref(),
+            source,
+        )?;
+        let ignore_patterns = Self::compile_patterns(
+            self.bundle_spec.bundle.ignore.as_ref(),
+            input.ignore.as_ref(),
+            source,
+        )?;
+
+        // Load tlpdb package data
+        let tlpdb_owners = input
+            .tlpdb
+            .as_ref()
+            .map(|x| self.load_tlpdb(x))
+            .transpose()
+            .with_context(|| format!("while loading tlpdb for source `{source}`"))?;
+        let tlpdb_selects = input.tlpdb.as_ref().map(|x| x.selects()).unwrap_or(false);
+
+        let license_map = input
+            .license_map
+            .as_ref()
+            .map(|x| LicenseMap::load(&self.bundle_dir.join(x)))
+            .transpose()?;
+
+        let mut source_backend = self
+            .registry
+            .open(&input.source(), &self.bundle_dir)
+            .with_context(|| format!("while opening source `{source}`"))?;
+        self.inputs.last_mut().unwrap().source = source_backend.description();
+        source_backend.set_link_policy(input.links.unwrap_or_default());
+        let skip_bad_entries = input.skip_bad_entries.unwrap_or(false);
+
+        if let Some(size) = source_backend.size_hint() {
+            debug!(
+                tectonic_log_source = "select",
+                "source `{source}` has about {} MiB of data",
+                size / (1024 * 1024)
             );
+        }
 
-            ignore
+        let hash = match source_backend.hash() {
+            Ok(x) => x,
+            Err(e) => {
+                error!(
+                    tectonic_log_source = "select",
+                    "could not compute hash of source `{source}`"
+                );
+                return Err(e);
+            }
         };
 
-        let mut source_backend = match &input.source {
-            BundleInputSource::Directory { path, .. } => Input::new_dir(self.bundle_dir.join(path)),
-            BundleInputSource::Tarball {
-                path,
-                root_dir,
-                hash,
-            } => {
-                let x = match Input::new_tarball(self.bundle_dir.join(path), root_dir.clone()) {
-                    Ok(x) => x,
-                    Err(e) => {
-                        error!(
-                            tectonic_log_source = "select",
-                            "could not add source `{source}` from tarball"
-                        );
-                        return Err(e);
-                    }
-                };
-                let hash = hash.clone();
+        if let Some(hash) = hash {
+            self.inputs.last_mut().unwrap().hash = Some(hash.clone());
+            if let Some(name) = source_backend.hash_file_name() {
                 self.add_file(
-                    Path::new("TAR-SHA256SUM"),
+                    Path::new(name),
                     source,
-                    &mut Cursor::new(format!("{}\n", x.hash().unwrap())),
+                    &mut Cursor::new(format!("{hash}\n")),
                     &HashMap::new(),
                 )?;
+            }
 
-                if x.hash().unwrap() != hash {
-                    if cli.allow_hash_mismatch {
-                        warn!(
-                            tectonic_log_source = "select",
-                            "hash of tarball for source `{source}` doesn't match expected value"
-                        );
+            if let Some(expected) = source_backend.expected_hash() {
+                if hash != expected {
+                    if allow_hash_mismatch {
                         warn!(
                             tectonic_log_source = "select",
-                            "expected: {}",
-                            x.hash().unwrap()
+                            "hash of source `{source}` doesn't match expected value"
                         );
-                        warn!(tectonic_log_source = "select", "got:      {}", hash);
+                        warn!(tectonic_log_source = "select", "expected: {expected}");
+                        warn!(tectonic_log_source = "select", "got:      {hash}");
                     } else {
                         error!(
                             tectonic_log_source = "select",
-                            "hash of tarball for source `{source}` doesn't match expected value"
-                        );
-                        error!(
-                            tectonic_log_source = "select",
-                            "expected: {}",
-                            x.hash().unwrap()
+                            "hash of source `{source}` doesn't match expected value"
                         );
-                        error!(tectonic_log_source = "select", "got:      {}", hash);
-                        bail!("hash of tarball for source `{source}` doesn't match expected value")
+                        error!(tectonic_log_source = "select", "expected: {expected}");
+                        error!(tectonic_log_source = "select", "got:      {hash}");
+                        bail!("hash of source `{source}` doesn't match expected value")
                     }
+                } else {
+                    info!(
+                        tectonic_log_source = "select",
+                        "OK, hash of source `{source}` matches bundle config"
+                    );
                 }
-                info!(
-                    tectonic_log_source = "select",
-                    "OK, tar hash matches bundle config"
-                );
-
-                x
             }
-        };
-
-        for x in source_backend.iter_files() {
-            let (rel_file_path, mut read) = x?;
+        }
 
-            let ignore = {
-                let f = format!("/{source}/{}", rel_file_path);
-                let mut ignore = false;
-                for pattern in &ignore_patterns {
-                    if pattern.is_match(&f) {
-                        ignore = true;
-                        break;
+        for x in source_backend
+            .iter_files()
+            .with_context(|| format!("while reading source `{source}`"))?
+        {
+            let (rel_file_path, mut read) = match x {
+                Ok(x) => x,
+                Err(e) => match e.downcast_ref::<BadEntry>() {
+                    Some(bad)
+                        if skip_bad_entries
+                            || (bad.non_utf8 && self.paths.non_utf8() != PathSeverity::Error) =>
+                    {
+                        if !(bad.non_utf8 && self.paths.non_utf8() == PathSeverity::Ignore) {
+                            warn!(
+                                tectonic_log_source = "select",
+                                "skipping {bad} in source `{source}`"
+                            );
+                        }
+                        self.stats.bad_entries += 1;
+                        self.bad_entries.push(format!("{source} {bad}"));
+                        continue;
                     }
-                }
-                ignore
+                    _ => return Err(e.context(format!("while reading source `{source}`"))),
+                },
             };
 
+            let f = format!("/{source}/{}", rel_file_path);
+            let owner = tlpdb_owners
+                .as_ref()
+                .and_then(|x| x.get(&rel_file_path))
+                .cloned();
+
+            // Skip files that aren't in a selected tlpdb package
+            if tlpdb_selects && owner.is_none() {
+                debug!(
+                    tectonic_log_source = "select",
+                    "skipping file {rel_file_path:?} from source `{source}` because it is not in a selected tlpdb package"
+                );
+                self.stats.not_included += 1;
+                continue;
+            }
+
+            // Skip files that aren't included.
+            // An empty include list includes everything.
+            if !include_patterns.is_empty() && !include_patterns.iter().any(|p| p.is_match(&f)) {
+                debug!(
+                    tectonic_log_source = "select",
+                    "skipping file {rel_file_path:?} from source `{source}` because it matches no include pattern"
+                );
+                self.stats.not_included += 1;
+                continue;
+            }
+
             // Skip ignored files
-            if ignore {
+            if ignore_patterns.iter().any(|p| p.is_match(&f)) {
                 debug!(
                     tectonic_log_source = "select",
                     "skipping file {rel_file_path:?} from source `{source}` because of ignore patterns"
@@ -493,6 +767,26 @@ impl FilePicker {
                 continue;
             }
 
+            // License map rules take precedence over tlpdb licenses
+            let license = license_map
+                .as_ref()
+                .and_then(|x| x.get(&rel_file_path))
+                .map(|x| x.to_owned())
+                .or_else(|| owner.as_ref().and_then(|x| x.license.clone()));
+
+            // Skip files with licenses we don't allow
+            if let Some(allowed) = &self.bundle_spec.bundle.allowed_licenses {
+                let l = license.as_deref().unwrap_or(UNKNOWN_LICENSE);
+                if !license::is_allowed(l, allowed) {
+                    debug!(
+                        tectonic_log_source = "select",
+                        "skipping file {rel_file_path:?} from source `{source}` because its license `{l}` is not allowed"
+                    );
+                    self.stats.license_excluded += 1;
+                    continue;
+                }
+            }
+
             // Debug info
             if self.filelist.len() % 1937 == 1936 {
                 info!(
@@ -507,11 +801,32 @@ impl FilePicker {
                 "adding file {rel_file_path:?} from source `{source}`"
             );
 
-            self.add_file(Path::new(&rel_file_path), source, &mut read, &diffs)
-                .with_context(|| format!("while adding file `{rel_file_path:?}`"))?;
+            if !self
+                .add_file(Path::new(&rel_file_path), source, &mut read, &diffs)
+                .with_context(|| format!("while adding file `{rel_file_path:?}`"))?
+            {
+                continue;
+            }
+            if let Some(entry) = self
+                .filelist
+                .get_mut(&Path::new(source).join(&rel_file_path))
+            {
+                entry.package = owner.map(|x| x.package);
+                entry.license = license.clone();
+                if let (Some(input), Some(hash)) = (self.inputs.last_mut(), &entry.hash) {
+                    input.files.push(FileRecord {
+                        path: rel_file_path.clone(),
+                        hash: hash.clone(),
+                        license,
+                    });
+                }
+            }
             added += 1;
         }
 
+        let links = source_backend.link_stats();
+        self.stats.links_followed += links.followed;
+        self.stats.links_skipped += links.skipped;
         self.stats.added.insert(source.to_owned(), added);
 
         Ok(())
@@ -554,7 +869,7 @@ impl FilePicker {
                 writeln!(file, "{s}")?;
             }
 
-            self.add_to_filelist(PathBuf::from("SEARCH"), Some(&path))?;
+            self.add_to_filelist(PathBuf::from("SEARCH"), Some(provenance::hash_file(&path)?))?;
 
             search
         };
@@ -564,6 +879,9 @@ impl FilePicker {
             // The hash is generated from the filelist, so we must add these before hashing.
             self.add_to_filelist(PathBuf::from("SHA256SUM"), None)?;
             self.add_to_filelist(PathBuf::from("FILELIST"), None)?;
+            if self.bundle_spec.bundle.embed_provenance.unwrap_or(false) {
+                self.add_to_filelist(PathBuf::from(PROVENANCE_FILE), None)?;
+            }
 
             let mut filelist_vec = Vec::from_iter(self.filelist.values());
             filelist_vec.sort_by(|a, b| a.path.cmp(&b.path));
@@ -582,17 +900,69 @@ impl FilePicker {
 
             let mut hasher = Sha256::new();
             let _ = std::io::copy(&mut fs::File::open(&filelist_path)?, &mut hasher)?;
-            let hash = hasher
-                .finalize()
-                .iter()
-                .map(|b| format!("{b:02x}"))
-                .collect::<Vec<_>>()
-                .concat();
+            let hash = hex::encode(&hasher.finalize());
 
             writeln!(file, "{hash}")?;
         }
 
+        // Generate license-report
+        {
+            let mut file = File::create(self.build_dir.join("license-report"))
+                .context("while writing license-report")?;
+
+            // Special files (like SHA256SUM) have no hash and no license
+            let mut entries = Vec::from_iter(self.filelist.values().filter_map(|x| {
+                x.hash.as_ref()?;
+                Some((x.license.as_deref().unwrap_or(UNKNOWN_LICENSE), &x.path))
+            }));
+            entries.sort();
+
+            let mut summary: Vec<(&str, usize)> = Vec::new();
+            for (license, _) in &entries {
+                match summary.last_mut() {
+                    Some((l, n)) if l == license => *n += 1,
+                    _ => summary.push((license, 1)),
+                }
+            }
+
+            writeln!(file, "[SUMMARY]")?;
+            for (license, count) in summary {
+                writeln!(file, "{count} {license}")?;
+            }
+
+            writeln!(file, "[FILES]")?;
+            for (license, path) in entries {
+                writeln!(file, "{license} {}", path.display())?;
+            }
+        }
+
+        // Generate bad-entries-report
+        if !self.bad_entries.is_empty() {
+            let mut file = File::create(self.build_dir.join("bad-entries-report"))
+                .context("while writing bad-entries-report")?;
+            for line in &self.bad_entries {
+                writeln!(file, "{line}")?;
+            }
+        }
+
         if save_debug_files {
+            // Generate package-report
+            if self.filelist.values().any(|x| x.package.is_some()) {
+                let mut file = File::create(self.build_dir.join("package-report"))
+                    .context("while writing package-report")?;
+
+                let mut entries = Vec::from_iter(
+                    self.filelist
+                        .values()
+                        .filter_map(|x| x.package.as_ref().map(|p| (p, &x.path))),
+                );
+                entries.sort();
+
+                for (package, path) in entries {
+                    writeln!(file, "{package} {}", path.display())?;
+                }
+            }
+
             // Generate search-report
             {
                 let mut file = File::create(self.build_dir.join("search-report"))
@@ -604,10 +974,11 @@ impl FilePicker {
                     }
                     let entry = entry
                         .into_path()
-                        .strip_prefix(&self.build_dir.join("content"))
+                        .strip_prefix(self.build_dir.join("content"))
                         .unwrap()
                         .to_owned();
                     let entry = PathBuf::from("/").join(entry);
+                    let entry = entry.to_string_lossy();
 
                     // Will this directory be searched?
                     let mut is_searched = false;
@@ -615,13 +986,13 @@ impl FilePicker {
                         if rule.ends_with("//") {
                             // Match start of patent path
                             // (cutting off the last slash from)
-                            if entry.starts_with(&rule[0..rule.len() - 1]) {
+                            if Path::new(&*entry).starts_with(&rule[0..rule.len() - 1]) {
                                 is_searched = true;
                                 break;
                             }
                         } else {
                             // Match full parent path
-                            if entry.to_str().unwrap() == rule {
+                            if entry == *rule {
                                 is_searched = true;
                                 break;
                             }
@@ -629,13 +1000,14 @@ impl FilePicker {
                     }
 
                     if !is_searched {
-                        let s = entry.to_str().unwrap();
-                        let t = s.matches('/').count();
-                        writeln!(file, "{}{s}", "\t".repeat(t - 1))?;
+                        let t = entry.matches('/').count();
+                        writeln!(file, "{}{entry}", "\t".repeat(t - 1))?;
                     }
                 }
             }
         }
+
+        self.stats.elapsed = self.started.elapsed();
         Ok(())
     }
 }
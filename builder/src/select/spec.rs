@@ -1,7 +1,8 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use serde::Deserialize;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
@@ -11,6 +12,16 @@ pub struct BundleSpec {
 }
 
 impl BundleSpec {
+    /// Load and validate the bundle specification at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("while reading `{}`", path.display()))?;
+        let spec: Self =
+            toml::from_str(&text).with_context(|| format!("while parsing `{}`", path.display()))?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
     /// Make sure this bundle specification is valid
     pub fn validate(&self) -> Result<()> {
         for i in &self.bundle.search_order {
@@ -24,6 +35,43 @@ impl BundleSpec {
             }
         }
 
+        for (name, input) in &self.inputs {
+            match (&input.source, &input.files) {
+                (Some(_), Some(_)) => {
+                    bail!("input `{name}` has both a `source` and inline `files`")
+                }
+                (None, None) => bail!("input `{name}` has no `source`"),
+                _ => {}
+            }
+        }
+
+        for (k, v) in self.bundle.meta.iter().flatten() {
+            if k.is_empty() || k.contains(['=', '\n', '[']) {
+                bail!("bad metadata key `{k}`");
+            }
+            if v.contains('\n') {
+                bail!("metadata value for `{k}` contains a newline");
+            }
+        }
+
+        if let Some(policy) = &self.bundle.path_policy {
+            if matches!(
+                policy.newline,
+                Some(PathSeverity::Warn | PathSeverity::Ignore)
+            ) {
+                bail!("path policy for `newline` must be `error` or `skip`");
+            }
+        }
+
+        for (name, group) in self.bundle.prefetch.iter().flatten() {
+            if name.is_empty() || name.contains([']', '\n']) {
+                bail!("bad prefetch group name `{name}`");
+            }
+            if group.files.is_none() && group.trace.is_none() {
+                bail!("prefetch group `{name}` has no files or trace");
+            }
+        }
+
         Ok(())
     }
 }
@@ -39,8 +87,89 @@ pub struct BundleConfig {
     /// Search paths for this bundle
     pub search_order: Vec<BundleSearchOrder>,
 
-    /// Files to ignore from this input
+    /// Files to include from all inputs.
+    /// These are combined with each input's own include patterns.
+    /// If an input has any include pattern (here or its own),
+    /// only its files matching one of them are added.
+    pub include: Option<Vec<String>>,
+
+    /// Files to ignore from all inputs.
+    /// These are applied after include patterns.
     pub ignore: Option<Vec<String>>,
+
+    /// If set, only files with these licenses are added.
+    pub allowed_licenses: Option<Vec<String>>,
+
+    /// If true, include this bundle's provenance file in its content.
+    pub embed_provenance: Option<bool>,
+
+    /// Free-form metadata, written to the bundle index
+    pub meta: Option<BTreeMap<String, String>>,
+
+    /// Groups of files clients may fetch together, by name
+    pub prefetch: Option<BTreeMap<String, BundlePrefetch>>,
+
+    /// How to handle file names that aren't safe in a bundle
+    pub path_policy: Option<PathPolicy>,
+}
+
+/// What to do with each kind of unsafe file name.
+/// See [`crate::select::pathpolicy`] for defaults.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PathPolicy {
+    /// Names with a newline. These can't be written to a file list,
+    /// so this must be `error` or `skip`.
+    pub newline: Option<PathSeverity>,
+
+    /// Names with other control characters
+    pub control: Option<PathSeverity>,
+
+    /// Names that aren't valid utf-8.
+    /// These can't be added, so `warn` skips them too.
+    pub non_utf8: Option<PathSeverity>,
+
+    /// Names that are the same as an earlier name, ignoring case
+    pub case_collision: Option<PathSeverity>,
+
+    /// Names that are the same as an earlier name after unicode normalization
+    pub normalization_collision: Option<PathSeverity>,
+
+    /// Names that aren't valid on Windows
+    pub windows: Option<PathSeverity>,
+}
+
+/// What to do with a file that breaks a path policy rule
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PathSeverity {
+    /// Add this file without a warning
+    #[serde(rename = "ignore")]
+    Ignore,
+
+    /// Add this file with a warning
+    #[serde(rename = "warn")]
+    Warn,
+
+    /// Skip this file with a warning
+    #[serde(rename = "skip")]
+    Skip,
+
+    /// Stop selecting files
+    #[serde(rename = "error")]
+    Error,
+}
+
+/// A group of files that are stored next to each other in the bundle,
+/// so that clients may fetch all of them in one request.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BundlePrefetch {
+    /// Files in this group. These are found using the
+    /// bundle's search order, just like Tectonic would.
+    pub files: Option<Vec<String>>,
+
+    /// A trace of the files in this group, relative to the bundle dir
+    pub trace: Option<PathBuf>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -52,21 +181,129 @@ pub enum BundleSearchOrder {
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct BundleInput {
-    pub source: BundleInputSource,
+    /// Where this input's files come from.
+    /// Inputs with inline `files` may leave this out.
+    pub source: Option<BundleInputSource>,
+
+    /// Files declared in the bundle specification, by path relative to this input's root.
+    /// This is a shortcut for an `inline` source.
+    pub files: Option<BTreeMap<String, toml::Value>>,
+
+    pub include: Option<Vec<String>>,
     pub ignore: Option<Vec<String>>,
     pub patch_dir: Option<PathBuf>,
     pub search_order: Option<Vec<String>>,
+    pub tlpdb: Option<BundleTlpdb>,
+    pub license_map: Option<PathBuf>,
+
+    /// Where this input's root goes in an exported TDS tree,
+    /// relative to the tree's root. Defaults to the tree's root.
+    pub tds_root: Option<PathBuf>,
+
+    /// What to do with symlinks and hardlinks in this input.
+    /// Defaults to `skip`.
+    pub links: Option<LinkPolicy>,
+
+    /// If true, skip entries of this input that can't be read
+    /// (like files with non-utf-8 names) instead of failing.
+    /// Skipped entries are listed in `bad-entries-report`.
+    pub skip_bad_entries: Option<bool>,
 }
 
+/// What to do with symlinks and hardlinks in an input
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LinkPolicy {
+    /// Add links with their target's contents.
+    /// Links that point outside the input's root are skipped with a warning.
+    #[serde(rename = "follow")]
+    Follow,
+
+    /// Like `follow`, but links may point outside the input's root.
+    /// Tarball links must still point inside the tarball.
+    #[serde(rename = "copy")]
+    Copy,
+
+    /// Skip links with a warning
+    #[default]
+    #[serde(rename = "skip")]
+    Skip,
+
+    /// Fail if this input has any links
+    #[serde(rename = "error")]
+    Error,
+}
+
+impl BundleInput {
+    /// This input's source. Inputs with inline `files` get an `inline` source.
+    pub fn source(&self) -> BundleInputSource {
+        match (&self.source, &self.files) {
+            (Some(source), _) => source.clone(),
+            (None, files) => BundleInputSource {
+                kind: "inline".to_owned(),
+                config: toml::Value::Table(toml::map::Map::from_iter([(
+                    "files".to_owned(),
+                    toml::Value::Table(files.clone().unwrap_or_default().into_iter().collect()),
+                )])),
+            },
+        }
+    }
+}
+
+/// Select files from an input using TeX Live's package database.
 #[derive(Debug, Deserialize, Clone)]
-pub enum BundleInputSource {
-    #[serde(rename = "dir")]
-    Directory { path: PathBuf },
-
-    #[serde(rename = "tarball")]
-    Tarball {
-        hash: String,
-        path: PathBuf,
-        root_dir: Option<PathBuf>,
-    },
+#[serde(deny_unknown_fields)]
+pub struct BundleTlpdb {
+    /// Path to `texlive.tlpdb`, relative to the bundle dir
+    pub path: PathBuf,
+
+    /// The directory in tlpdb paths that corresponds to this input's root.
+    /// Defaults to `texmf-dist`.
+    pub prefix: Option<String>,
+
+    /// Packages to include
+    pub packages: Option<Vec<String>>,
+
+    /// Collections to include
+    pub collections: Option<Vec<String>>,
+
+    /// Schemes to include
+    pub schemes: Option<Vec<String>>,
+}
+
+impl BundleTlpdb {
+    /// Are we selecting files with this tlpdb?
+    /// If we aren't, the tlpdb is only used to look up package names.
+    pub fn selects(&self) -> bool {
+        self.packages.is_some() || self.collections.is_some() || self.schemes.is_some()
+    }
+}
+
+/// Where an input's files come from.
+///
+/// In TOML, this is a table with exactly one key, like `source.dir.path = "include"`.
+/// That key is this source's `kind`, and its value is the source's configuration,
+/// which is parsed by the input backend registered for that kind.
+/// See [`crate::select::input::InputRegistry`].
+#[derive(Debug, Clone)]
+pub struct BundleInputSource {
+    /// This source's tag, like `dir` or `tarball`
+    pub kind: String,
+
+    /// This source's configuration
+    pub config: toml::Value,
+}
+
+impl<'de> Deserialize<'de> for BundleInputSource {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table = BTreeMap::<String, toml::Value>::deserialize(deserializer)?;
+        if table.len() != 1 {
+            return Err(serde::de::Error::custom(format!(
+                "an input source must have exactly one kind, got {}",
+                table.len()
+            )));
+        }
+
+        let (kind, config) = table.into_iter().next().unwrap();
+        Ok(Self { kind, config })
+    }
 }
@@ -0,0 +1,294 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+use tracing::warn;
+
+/// A single package record from `texlive.tlpdb`
+#[derive(Debug, Default)]
+pub struct TlpdbPackage {
+    /// `Package`, `Collection`, `Scheme`, `TLCore`, etc.
+    pub category: String,
+
+    /// Names of packages this package depends on
+    pub depends: Vec<String>,
+
+    /// Paths of this package's runtime files,
+    /// relative to the TeX Live root (e.g `texmf-dist/tex/...`)
+    pub runfiles: Vec<String>,
+
+    /// This package's license, from `catalogue-license`.
+    /// Multiple licenses are separated by spaces.
+    pub license: Option<String>,
+}
+
+/// The package a file belongs to
+#[derive(Debug, Clone)]
+pub struct TlpdbOwner {
+    /// The package's name
+    pub package: String,
+
+    /// The package's license, if it has one
+    pub license: Option<String>,
+}
+
+/// A parsed TeX Live package database.
+pub struct Tlpdb {
+    packages: HashMap<String, TlpdbPackage>,
+}
+
+impl Tlpdb {
+    /// Load and parse a `texlive.tlpdb` file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("while reading tlpdb `{}`", path.display()))?;
+        Self::parse(&text)
+    }
+
+    /// Parse the contents of a `texlive.tlpdb` file.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut packages = HashMap::new();
+        let mut name: Option<String> = None;
+        let mut package = TlpdbPackage::default();
+
+        // True if the indented lines we're reading are runfiles
+        let mut in_runfiles = false;
+
+        for (n, line) in text.lines().enumerate() {
+            // Empty lines separate records
+            if line.is_empty() {
+                if let Some(name) = name.take() {
+                    packages.insert(name, std::mem::take(&mut package));
+                }
+                in_runfiles = false;
+                continue;
+            }
+
+            // Indented lines are file lists
+            if let Some(file) = line.strip_prefix(' ') {
+                if in_runfiles {
+                    package.runfiles.push(file.to_owned());
+                }
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            in_runfiles = false;
+            match key {
+                "name" => {
+                    if name.is_some() {
+                        bail!("tlpdb line {}: record has two names", n + 1);
+                    }
+                    name = Some(value.to_owned());
+                }
+                "category" => package.category = value.to_owned(),
+                "depend" => package.depends.push(value.to_owned()),
+                "runfiles" => in_runfiles = true,
+                "catalogue-license" => package.license = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+
+        if let Some(name) = name.take() {
+            packages.insert(name, package);
+        }
+
+        Ok(Self { packages })
+    }
+
+    /// Make sure `name` exists and has the given category.
+    /// Pass `None` as `category` to accept any category.
+    pub fn check(&self, name: &str, category: Option<&str>) -> Result<()> {
+        match (self.packages.get(name), category) {
+            (None, _) => bail!("tlpdb has no package named `{name}`"),
+            (Some(p), Some(c)) if p.category != c => {
+                bail!("`{name}` is a {}, not a {c}", p.category)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Resolve the given package names and all their dependencies.
+    /// Returns a set of package names.
+    ///
+    /// Architecture-specific dependencies (like `tex.ARCH`) are skipped,
+    /// since bundles never contain binaries.
+    pub fn resolve<'a>(&self, names: impl Iterator<Item = &'a String>) -> HashSet<String> {
+        let mut resolved = HashSet::new();
+        let mut queue: Vec<&str> = names.map(|x| x.as_str()).collect();
+
+        while let Some(name) = queue.pop() {
+            if name.ends_with(".ARCH") || resolved.contains(name) {
+                continue;
+            }
+
+            let package = match self.packages.get(name) {
+                Some(x) => x,
+                None => {
+                    warn!(
+                        tectonic_log_source = "select",
+                        "tlpdb dependency `{name}` does not exist, skipping"
+                    );
+                    continue;
+                }
+            };
+
+            resolved.insert(name.to_owned());
+            queue.extend(package.depends.iter().map(|x| x.as_str()));
+        }
+
+        resolved
+    }
+
+    /// Map every runfile of the given packages to the package it belongs to.
+    /// Only files under `prefix` are kept, and `prefix` is removed from their paths.
+    /// Pass `None` as `packages` to map files from every package.
+    pub fn file_owners(
+        &self,
+        packages: Option<&HashSet<String>>,
+        prefix: &str,
+    ) -> HashMap<String, TlpdbOwner> {
+        let prefix = format!("{}/", prefix.trim_end_matches('/'));
+        let mut owners = HashMap::new();
+
+        for (name, package) in &self.packages {
+            if packages.map(|x| !x.contains(name)).unwrap_or(false) {
+                continue;
+            }
+
+            for file in &package.runfiles {
+                let file = match file
+                    .strip_prefix(&prefix)
+                    .or_else(|| file.strip_prefix("RELOC/"))
+                {
+                    Some(x) => x,
+                    None => continue,
+                };
+                owners.insert(
+                    file.to_owned(),
+                    TlpdbOwner {
+                        package: name.clone(),
+                        license: package.license.clone(),
+                    },
+                );
+            }
+        }
+
+        owners
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TLPDB: &str = "\
+name scheme-test
+category Scheme
+depend collection-basic
+depend tex.ARCH
+
+name collection-basic
+category Collection
+depend foo
+depend bar
+depend missing
+
+name foo
+category Package
+catalogue-license lppl1.3c
+runfiles size=2
+ texmf-dist/tex/latex/foo/foo.sty
+ RELOC/tex/latex/foo/foo.cls
+docfiles size=1
+ texmf-dist/doc/latex/foo/foo.pdf
+
+name bar
+category Package
+depend foo
+runfiles size=1
+ texmf-dist/tex/latex/bar/bar.sty
+ texmf/web2c/bar.cnf
+";
+
+    fn names(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_records() {
+        let db = Tlpdb::parse(TLPDB).unwrap();
+        assert_eq!(db.packages.len(), 4);
+
+        let foo = &db.packages["foo"];
+        assert_eq!(foo.category, "Package");
+        assert_eq!(foo.license.as_deref(), Some("lppl1.3c"));
+        // Doc files aren't runfiles
+        assert_eq!(
+            foo.runfiles,
+            [
+                "texmf-dist/tex/latex/foo/foo.sty",
+                "RELOC/tex/latex/foo/foo.cls"
+            ]
+        );
+
+        assert_eq!(db.packages["bar"].depends, ["foo"]);
+        assert_eq!(db.packages["bar"].license, None);
+    }
+
+    #[test]
+    fn parse_rejects_two_names() {
+        assert!(Tlpdb::parse("name a\nname b\n").is_err());
+    }
+
+    #[test]
+    fn check_category() {
+        let db = Tlpdb::parse(TLPDB).unwrap();
+        assert!(db.check("scheme-test", Some("Scheme")).is_ok());
+        assert!(db.check("collection-basic", Some("Scheme")).is_err());
+        assert!(db.check("foo", None).is_ok());
+        assert!(db.check("nothing", None).is_err());
+    }
+
+    #[test]
+    fn resolve_scheme() {
+        let db = Tlpdb::parse(TLPDB).unwrap();
+        let resolved = db.resolve(names(&["scheme-test"]).iter());
+        let mut resolved = Vec::from_iter(resolved);
+        resolved.sort();
+
+        // `tex.ARCH` and `missing` are skipped
+        assert_eq!(resolved, ["bar", "collection-basic", "foo", "scheme-test"]);
+    }
+
+    #[test]
+    fn resolve_package() {
+        let db = Tlpdb::parse(TLPDB).unwrap();
+        let mut resolved = Vec::from_iter(db.resolve(names(&["bar"]).iter()));
+        resolved.sort();
+        assert_eq!(resolved, ["bar", "foo"]);
+    }
+
+    #[test]
+    fn file_owners() {
+        let db = Tlpdb::parse(TLPDB).unwrap();
+        let only_foo = HashSet::from(["foo".to_owned()]);
+
+        let owners = db.file_owners(Some(&only_foo), "texmf-dist/");
+        let mut files = Vec::from_iter(owners.keys());
+        files.sort();
+        assert_eq!(files, ["tex/latex/foo/foo.cls", "tex/latex/foo/foo.sty"]);
+        assert_eq!(owners["tex/latex/foo/foo.sty"].package, "foo");
+        assert_eq!(
+            owners["tex/latex/foo/foo.sty"].license.as_deref(),
+            Some("lppl1.3c")
+        );
+
+        // Files outside the prefix are left out
+        let owners = db.file_owners(None, "texmf-dist");
+        assert!(owners.contains_key("tex/latex/bar/bar.sty"));
+        assert!(!owners.keys().any(|x| x.contains("bar.cnf")));
+    }
+}
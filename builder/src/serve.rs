@@ -0,0 +1,432 @@
+use crate::read::bundlev1::{BundleV1Reader, IndexEntry};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use tracing::{info, warn};
+
+/// A bundle we're serving
+struct ServedBundle {
+    header_size: u64,
+    index_start: u64,
+    index_gzip_len: u64,
+
+    /// True if this bundle's files are stored in parts
+    split: bool,
+
+    /// All files in this bundle, sorted by start byte
+    filelist: Vec<IndexEntry>,
+}
+
+/// A file we're serving: a bundle, or one part of a split bundle
+struct ServedFile {
+    path: PathBuf,
+    len: u64,
+
+    /// The bundle hash, used as this file's ETag.
+    /// Parts add their name to this hash.
+    etag: String,
+
+    bundle: Rc<ServedBundle>,
+
+    /// If this is a part, the position of its first byte among all parts
+    part_start: Option<u64>,
+}
+
+impl ServedBundle {
+    /// Load the bundle at `path`, returning it and all its parts
+    fn load(path: &Path) -> Result<Vec<(String, ServedFile)>> {
+        let reader = BundleV1Reader::open(path)?;
+        let hash = reader.header.hash_string();
+        let parts = reader.index.parts.clone();
+        let mut filelist = reader.index.filelist;
+        filelist.sort_by_key(|x| x.start);
+
+        let bundle = Rc::new(Self {
+            index_start: reader.header.index_start,
+            index_gzip_len: reader.header.index_gzip_len,
+            header_size: reader.header.size(),
+            split: !parts.is_empty(),
+            filelist,
+        });
+
+        let mut files = vec![(
+            file_name(path)?,
+            ServedFile {
+                path: path.to_path_buf(),
+                len: path.metadata()?.len(),
+                etag: format!("\"{hash}\""),
+                bundle: bundle.clone(),
+                part_start: None,
+            },
+        )];
+
+        for part in parts {
+            let part_path = path.with_file_name(&part.name);
+            files.push((
+                part.name.clone(),
+                ServedFile {
+                    len: part_path
+                        .metadata()
+                        .with_context(|| format!("while loading `{}`", part_path.display()))?
+                        .len(),
+                    path: part_path,
+                    etag: format!("\"{hash}-{}\"", part.name),
+                    bundle: bundle.clone(),
+                    part_start: Some(part.start),
+                },
+            ));
+        }
+
+        Ok(files)
+    }
+
+    /// Describe what the bytes `start..=end` of this bundle contain.
+    /// If `part_start` is given, these are bytes of a part starting there.
+    fn describe_range(&self, start: u64, end: u64, part_start: Option<u64>) -> String {
+        let mut parts = Vec::new();
+
+        if part_start.is_none() {
+            if start < self.header_size {
+                parts.push("<header>".to_owned());
+            }
+            if start < self.index_start + self.index_gzip_len && end >= self.index_start {
+                parts.push("<index>".to_owned());
+            }
+
+            // Split bundles don't keep files here
+            if self.split {
+                return parts.join(", ");
+            }
+        }
+
+        let (start, end) = match part_start {
+            Some(x) => (start + x, end + x),
+            None => (start, end),
+        };
+
+        // First file that ends after `start`
+        let first = self
+            .filelist
+            .partition_point(|x| x.start + x.gzip_len <= start);
+        let files: Vec<&str> = self.filelist[first..]
+            .iter()
+            .take_while(|x| x.start <= end)
+            .map(|x| x.path.as_str())
+            .collect();
+
+        if files.len() > 5 {
+            parts.extend(files[..5].iter().map(|x| x.to_string()));
+            parts.push(format!("and {} more", files.len() - 5));
+        } else {
+            parts.extend(files.iter().map(|x| x.to_string()));
+        }
+
+        parts.join(", ")
+    }
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    Ok(path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| anyhow!("bad bundle path `{}`", path.display()))?
+        .to_owned())
+}
+
+/// Parse the value of a `Range` header.
+/// Returns an inclusive byte range, or `None` if the header should be ignored.
+///
+/// Headers we can't parse are ignored, as RFC 7233 asks.
+/// Only ranges that are valid but outside this file are an error.
+fn parse_range(value: &str, len: u64) -> Result<Option<(u64, u64)>> {
+    let spec = match value.trim().strip_prefix("bytes=") {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    // We only serve single ranges. Clients that ask for
+    // many ranges at once get the whole file instead.
+    if spec.contains(',') {
+        warn!(
+            tectonic_log_source = "serve",
+            "multi-range requests aren't supported, sending whole bundle"
+        );
+        return Ok(None);
+    }
+
+    let number = |x: &str| -> Option<u64> {
+        if x.is_empty() || !x.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        x.parse().ok()
+    };
+
+    let (a, b) = match spec.split_once('-') {
+        Some((a, b)) => (a.trim(), b.trim()),
+        None => {
+            warn!(
+                tectonic_log_source = "serve",
+                "ignoring bad range `{value}`"
+            );
+            return Ok(None);
+        }
+    };
+
+    let range = match (a, b) {
+        // Suffix range: the last `b` bytes
+        ("", b) => number(b).map(|n| (len.saturating_sub(n), n)),
+        (a, "") => number(a).map(|a| (a, u64::MAX)),
+        (a, b) => match (number(a), number(b)) {
+            (Some(a), Some(b)) if a <= b => Some((a, b)),
+            _ => None,
+        },
+    };
+
+    let (start, end) = match range {
+        Some(x) => x,
+        None => {
+            warn!(
+                tectonic_log_source = "serve",
+                "ignoring bad range `{value}`"
+            );
+            return Ok(None);
+        }
+    };
+
+    // A suffix range's `end` is its length
+    let suffix = a.is_empty();
+    if start >= len || (suffix && end == 0) {
+        bail!("unsatisfiable range `{value}`");
+    }
+
+    if suffix {
+        Ok(Some((start, len - 1)))
+    } else {
+        Ok(Some((start, end.min(len - 1))))
+    }
+}
+
+/// Does the value of an `If-None-Match` header match `etag`?
+/// This uses weak comparison, as RFC 7232 asks.
+fn none_match(value: &str, etag: &str) -> bool {
+    value
+        .split(',')
+        .map(|x| x.trim())
+        .any(|x| x == "*" || x.trim_start_matches("W/") == etag)
+}
+
+/// Does the value of an `If-Range` header match `etag`?
+/// We only compare entity tags, strongly. Dates never match,
+/// since our files have no modification time.
+fn if_range(value: &str, etag: &str) -> bool {
+    value.trim() == etag
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn handle(request: Request, bundles: &HashMap<String, ServedFile>) -> Result<()> {
+    let method = request.method().clone();
+    let url = request.url().to_owned();
+
+    if method != Method::Get && method != Method::Head {
+        warn!(tectonic_log_source = "serve", "{method} {url}: not allowed");
+        request.respond(Response::empty(StatusCode(405)))?;
+        return Ok(());
+    }
+
+    // Ignore any query string
+    let name = url
+        .split(['?', '#'])
+        .next()
+        .unwrap()
+        .trim_start_matches('/');
+    let bundle = match bundles.get(name) {
+        Some(x) => x,
+        None => {
+            warn!(tectonic_log_source = "serve", "{method} {url}: not found");
+            request.respond(Response::empty(StatusCode(404)))?;
+            return Ok(());
+        }
+    };
+
+    let get_header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str().to_owned())
+    };
+
+    // The client already has this file
+    if let Some(value) = get_header("If-None-Match") {
+        if none_match(&value, &bundle.etag) {
+            info!(
+                tectonic_log_source = "serve",
+                "{method} {url}: not modified"
+            );
+            request.respond(
+                Response::empty(StatusCode(304)).with_header(header("ETag", &bundle.etag)),
+            )?;
+            return Ok(());
+        }
+    }
+
+    // `If-Range` asks for the whole file if the client's copy is out of date.
+    let if_range = get_header("If-Range").map(|x| if_range(&x, &bundle.etag));
+    let range = match if_range {
+        Some(false) => None,
+        _ => get_header("Range").map(|x| parse_range(&x, bundle.len)),
+    };
+
+    let (status, start, end) = match range {
+        None | Some(Ok(None)) => (200, 0, bundle.len - 1),
+        Some(Ok(Some((start, end)))) => (206, start, end),
+        Some(Err(e)) => {
+            warn!(tectonic_log_source = "serve", "{method} {url}: {e}");
+            request.respond(
+                Response::empty(StatusCode(416))
+                    .with_header(header("Content-Range", &format!("bytes */{}", bundle.len))),
+            )?;
+            return Ok(());
+        }
+    };
+
+    let len = end - start + 1;
+    info!(
+        tectonic_log_source = "serve",
+        "{method} {url} bytes {start}-{end} ({len} bytes): {}",
+        bundle.bundle.describe_range(start, end, bundle.part_start)
+    );
+
+    let mut file = File::open(&bundle.path)?;
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut response = Response::new(
+        StatusCode(status),
+        vec![
+            header("Content-Type", "application/octet-stream"),
+            header("Accept-Ranges", "bytes"),
+            header("ETag", &bundle.etag),
+        ],
+        file.take(len),
+        Some(len as usize),
+        None,
+    );
+    if status == 206 {
+        response.add_header(header(
+            "Content-Range",
+            &format!("bytes {start}-{end}/{}", bundle.len),
+        ));
+    }
+
+    request.respond(response)?;
+    Ok(())
+}
+
+/// Serve the given bundles over HTTP until we're killed.
+/// Each bundle and bundle part is served at `/<file name>`.
+pub fn serve(paths: &[PathBuf], address: &str) -> Result<()> {
+    let mut bundles = HashMap::new();
+    for path in paths {
+        let files = ServedBundle::load(path)
+            .with_context(|| format!("while loading `{}`", path.display()))?;
+
+        for (name, file) in files {
+            if bundles.contains_key(&name) {
+                bail!("two files are named `{name}`");
+            }
+            bundles.insert(name, file);
+        }
+    }
+
+    let server = Server::http(address).map_err(|e| anyhow!("could not start server: {e}"))?;
+    for name in bundles.keys() {
+        info!(
+            tectonic_log_source = "serve",
+            "serving http://{address}/{name}"
+        );
+    }
+
+    for request in server.incoming_requests() {
+        if let Err(e) = handle(request, &bundles) {
+            warn!(
+                tectonic_log_source = "serve",
+                "error while handling request: {e}"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range() {
+        assert_eq!(parse_range("bytes=0-99", 1000).unwrap(), Some((0, 99)));
+        assert_eq!(parse_range(" bytes=10-10", 1000).unwrap(), Some((10, 10)));
+        // Open and overlong ranges end at the end of the file
+        assert_eq!(parse_range("bytes=900-", 1000).unwrap(), Some((900, 999)));
+        assert_eq!(
+            parse_range("bytes=900-5000", 1000).unwrap(),
+            Some((900, 999))
+        );
+    }
+
+    #[test]
+    fn suffix_range() {
+        assert_eq!(parse_range("bytes=-100", 1000).unwrap(), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-5000", 1000).unwrap(), Some((0, 999)));
+        assert!(parse_range("bytes=-0", 1000).is_err());
+    }
+
+    #[test]
+    fn ignored_range() {
+        for value in [
+            "items=0-10",
+            "bytes=0-10,20-30",
+            "bytes=10",
+            "bytes=-",
+            "bytes=a-b",
+            "bytes=+1-2",
+            "bytes=20-10",
+        ] {
+            assert_eq!(parse_range(value, 1000).unwrap(), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn unsatisfiable_range() {
+        assert!(parse_range("bytes=1000-", 1000).is_err());
+        assert!(parse_range("bytes=1000-1001", 1000).is_err());
+    }
+
+    #[test]
+    fn if_none_match() {
+        let etag = "\"abc\"";
+        assert!(none_match("\"abc\"", etag));
+        assert!(none_match("W/\"abc\"", etag));
+        assert!(none_match("\"x\", \"abc\"", etag));
+        assert!(none_match("*", etag));
+        assert!(!none_match("\"abcd\"", etag));
+        assert!(!none_match("abc", etag));
+    }
+
+    #[test]
+    fn if_range_match() {
+        let etag = "\"abc\"";
+        assert!(if_range(" \"abc\" ", etag));
+        assert!(!if_range("W/\"abc\"", etag));
+        assert!(!if_range("Tue, 14 Nov 2023 22:13:20 GMT", etag));
+    }
+}
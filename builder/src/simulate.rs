@@ -0,0 +1,136 @@
+use crate::{
+    pack::bundlev1::HEADER_SIZE, read::bundlev1::BundleV1Reader, search::SearchIndex,
+    trace::load_trace,
+};
+use anyhow::{bail, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+use tracing::debug;
+
+/// Rules for merging range requests
+pub struct CoalesceRules {
+    /// Merge two ranges if there are at most this many unneeded bytes between them
+    pub max_gap: u64,
+
+    /// Never make a request longer than this
+    pub max_request: Option<u64>,
+
+    /// If true, fetch files in bundle order instead of trace order.
+    /// This models a client that knows every file it needs up front.
+    pub sorted: bool,
+}
+
+/// A simulated range request
+struct RangeRequest {
+    start: u64,
+    end: u64,
+
+    /// Bytes in this range we actually need
+    useful: u64,
+}
+
+/// Simulate the range requests a client would make to load every file in
+/// `trace` from `bundle`, and print a report.
+pub fn simulate(bundle: &Path, trace: &Path, rules: &CoalesceRules) -> Result<()> {
+    let reader = BundleV1Reader::open(bundle)?;
+    let index = &reader.index;
+
+    let search_rules = match index.search.get(&index.default_search) {
+        Some(x) => x,
+        None => bail!("this bundle has no default search order"),
+    };
+    let search = SearchIndex::new(index.filelist.iter().map(|x| x.path.as_str()), search_rules);
+    let entries: HashMap<&str, _> = index
+        .filelist
+        .iter()
+        .map(|x| (x.path.as_str(), x))
+        .collect();
+
+    let names = load_trace(trace)?;
+
+    // Blobs we need to fetch, as `(start, len)`.
+    // Many names may resolve to the same file, which we only fetch once.
+    let mut blobs = Vec::new();
+    let mut fetched = HashSet::new();
+    let mut missing = 0usize;
+    for name in &names {
+        match search.resolve(name) {
+            Some(path) => {
+                if fetched.insert(path) {
+                    let e = entries[path];
+                    blobs.push((e.start, e.gzip_len));
+                }
+            }
+            None => {
+                debug!(
+                    tectonic_log_source = "simulate",
+                    "`{name}` is not in this bundle"
+                );
+                missing += 1;
+            }
+        }
+    }
+
+    if rules.sorted {
+        blobs.sort();
+    }
+
+    let mut requests: Vec<RangeRequest> = Vec::new();
+    for (start, len) in blobs {
+        let end = start + len;
+
+        if let Some(last) = requests.last_mut() {
+            // Requests can't span two parts of a split bundle
+            let same_part =
+                index.part_of(start).map(|x| x.start) == index.part_of(last.start).map(|x| x.start);
+            let mergeable = same_part
+                && start >= last.end
+                && start - last.end <= rules.max_gap
+                && rules
+                    .max_request
+                    .map(|m| end - last.start <= m)
+                    .unwrap_or(true);
+
+            if mergeable {
+                last.end = end;
+                last.useful += len;
+                continue;
+            }
+        }
+
+        requests.push(RangeRequest {
+            start,
+            end,
+            useful: len,
+        });
+    }
+
+    // A client can't know a bundle's header size before reading its version,
+    // so it fetches a normal header first, and then the rest of a wide header.
+    // Only then does it know where the index is. These are `(start, len)`.
+    let mut index_requests = vec![(0, HEADER_SIZE)];
+    if reader.header.size() > HEADER_SIZE {
+        index_requests.push((HEADER_SIZE, reader.header.size() - HEADER_SIZE));
+    }
+    index_requests.push((reader.header.index_start, reader.header.index_gzip_len));
+
+    let transferred: u64 = requests.iter().map(|x| x.end - x.start).sum();
+    let useful: u64 = requests.iter().map(|x| x.useful).sum();
+
+    println!("files in trace:      {}", names.len());
+    println!("files fetched:       {}", fetched.len());
+    println!("files not in bundle: {missing}");
+    println!("range requests:      {}", requests.len());
+    println!(
+        "index requests:      {} ({} bytes)",
+        index_requests.len(),
+        index_requests.iter().map(|x| x.1).sum::<u64>()
+    );
+    println!("bytes transferred:   {transferred}");
+    println!("useful bytes:        {useful}");
+    println!("wasted bytes:        {}", transferred - useful);
+
+    Ok(())
+}
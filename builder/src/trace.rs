@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use std::{collections::HashSet, fs, path::Path};
+
+/// The kinds of trace files we understand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TraceFormat {
+    /// TeX `-recorder` output, with `PWD`, `INPUT`, and `OUTPUT` lines
+    Fls,
+
+    /// A Tectonic log, with `note: downloading <file>` lines
+    TectonicLog,
+
+    /// One file name per line
+    List,
+}
+
+impl TraceFormat {
+    /// Guess the format of a trace from its contents
+    fn detect(text: &str) -> Self {
+        let lines = || text.lines().map(|x| x.trim());
+
+        if lines().any(|x| x.starts_with("PWD ") || x.starts_with("INPUT ")) {
+            Self::Fls
+        } else if lines().any(|x| {
+            x.starts_with("note: ") || x.starts_with("warning: ") || x.starts_with("error: ")
+        }) {
+            Self::TectonicLog
+        } else {
+            Self::List
+        }
+    }
+
+    /// The file name on `line`, if it has one
+    fn file_name(self, line: &str) -> Option<&str> {
+        match self {
+            Self::Fls => line.strip_prefix("INPUT "),
+            Self::TectonicLog => {
+                let (_, x) = line.strip_prefix("note: ")?.split_once("downloading ")?;
+                let x = x.trim().trim_matches('`');
+
+                // Skip `downloading index <url>`, and anything else that isn't one name
+                if x.is_empty() || x.starts_with("index ") || x.contains(char::is_whitespace) {
+                    None
+                } else {
+                    Some(x)
+                }
+            }
+            Self::List => (!line.starts_with('#')).then_some(line),
+        }
+    }
+}
+
+/// Load a list of files a compile opened.
+///
+/// This understands TeX `-recorder` output (`.fls` files),
+/// Tectonic logs, and plain lists with one file name per line
+/// (lines starting with `#` are ignored). The format is detected
+/// from the trace's contents, and only lines that name a file in that
+/// format are read. Absolute paths are reduced to their file name.
+/// Each name is only returned once, in the order it was first opened.
+pub fn load_trace(path: &Path) -> Result<Vec<String>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("while reading trace `{}`", path.display()))?;
+    let format = TraceFormat::detect(&text);
+
+    let mut seen = HashSet::new();
+    let mut names = Vec::new();
+
+    for line in text.lines() {
+        let name = match format.file_name(line.trim()) {
+            Some(x) => x.trim(),
+            None => continue,
+        };
+
+        let name = if name.starts_with('/') {
+            name.rsplit('/').next().unwrap()
+        } else {
+            name
+        };
+
+        if name.is_empty() || !seen.insert(name.to_owned()) {
+            continue;
+        }
+        names.push(name.to_owned());
+    }
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_fls() {
+        let text = "PWD /home/user/doc\nINPUT /usr/share/texmf/article.cls\nOUTPUT doc.log\n";
+        assert_eq!(TraceFormat::detect(text), TraceFormat::Fls);
+        assert_eq!(TraceFormat::Fls.file_name("OUTPUT doc.log"), None);
+        assert_eq!(
+            TraceFormat::Fls.file_name("INPUT article.cls"),
+            Some("article.cls")
+        );
+    }
+
+    #[test]
+    fn detect_tectonic_log() {
+        let text = "note: downloading index https://example.com/bundle\n\
+                    note: downloading `article.cls`\n\
+                    note: Running TeX ...\n";
+        let format = TraceFormat::detect(text);
+        assert_eq!(format, TraceFormat::TectonicLog);
+
+        let names: Vec<_> = text.lines().filter_map(|x| format.file_name(x)).collect();
+        assert_eq!(names, ["article.cls"]);
+    }
+
+    #[test]
+    fn detect_list() {
+        let text = "# Files\narticle.cls\nsize10.clo\n";
+        let format = TraceFormat::detect(text);
+        assert_eq!(format, TraceFormat::List);
+
+        let names: Vec<_> = text.lines().filter_map(|x| format.file_name(x)).collect();
+        assert_eq!(names, ["article.cls", "size10.clo"]);
+    }
+}